@@ -0,0 +1,264 @@
+//! Declarative cluster property aggregations, e.g. `{"revenue": "sum"}`,
+//! computed by the clustering engine while it builds each zoom level.
+
+use geojson::JsonObject;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::supercluster::ReduceError;
+use crate::supercluster::Reducer;
+
+const NAMES: &str = "sum, min, max, mean, count, count_distinct";
+
+/// Properties the engine sets on every cluster, which aggregations would
+/// otherwise be overwritten by.
+const RESERVED: [&str; 5] = ["cluster", "cluster_id", "point_count", "point_count_abbreviated", "weight_sum"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Aggregation {
+    Sum,
+    Min,
+    Max,
+    /// Accumulated as `{"sum": .., "count": ..}` and divided out at the end.
+    Mean,
+    /// Number of points with a non-null value.
+    Count,
+    /// Accumulated as an object keyed by the distinct values as JSON, so that
+    /// merging clusters looks values up rather than scanning them, and
+    /// counted at the end.
+    CountDistinct,
+}
+
+impl Aggregation {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sum" => Some(Aggregation::Sum),
+            "min" => Some(Aggregation::Min),
+            "max" => Some(Aggregation::Max),
+            "mean" => Some(Aggregation::Mean),
+            "count" => Some(Aggregation::Count),
+            "count_distinct" => Some(Aggregation::CountDistinct),
+            _ => None,
+        }
+    }
+}
+
+/// Aggregations keyed by the point property they read; each result is
+/// emitted on clusters under the same name.
+#[derive(Debug)]
+pub struct Aggregations {
+    fields: Vec<(String, Aggregation)>,
+}
+
+impl Aggregations {
+    /// Parse `(property, aggregation name)` pairs, failing on the first
+    /// unknown aggregation name or property named like a cluster property.
+    pub fn parse(spec: impl IntoIterator<Item = (String, String)>) -> Result<Self, String> {
+        let mut fields = Vec::new();
+        for (property, name) in spec {
+            if RESERVED.contains(&property.as_str()) {
+                return Err(format!(
+                    "Cannot aggregate property '{}', clusters already have a property of that name",
+                    property
+                ));
+            }
+            let aggregation = Aggregation::parse(&name).ok_or_else(|| {
+                format!(
+                    "Unknown aggregation '{}' for property '{}', expected one of {}",
+                    name, property, NAMES
+                )
+            })?;
+            fields.push((property, aggregation));
+        }
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(Aggregations { fields })
+    }
+}
+
+impl Reducer for Aggregations {
+    fn map(&self, properties: &JsonObject) -> Result<JsonObject, ReduceError> {
+        let mut mapped = JsonObject::new();
+        for (property, aggregation) in &self.fields {
+            let value = match properties.get(property) {
+                Some(Value::Null) | None => continue,
+                Some(value) => value,
+            };
+            let mapped_value = match aggregation {
+                Aggregation::Sum | Aggregation::Min | Aggregation::Max if value.is_number() => {
+                    value.clone()
+                }
+                Aggregation::Mean if value.is_number() => json!({"sum": value, "count": 1}),
+                Aggregation::Count => json!(1),
+                Aggregation::CountDistinct => json!({ value.to_string(): true }),
+                _ => continue,
+            };
+            mapped.insert(property.clone(), mapped_value);
+        }
+        Ok(mapped)
+    }
+
+    fn reduce(&self, accumulated: &mut JsonObject, properties: &JsonObject) -> Result<(), ReduceError> {
+        for (property, aggregation) in &self.fields {
+            let value = match properties.get(property) {
+                Some(value) => value,
+                None => continue,
+            };
+            let current = match accumulated.get_mut(property) {
+                Some(current) => current,
+                None => {
+                    accumulated.insert(property.clone(), value.clone());
+                    continue;
+                }
+            };
+            match aggregation {
+                Aggregation::Sum | Aggregation::Count => *current = add(current, value),
+                Aggregation::Min => {
+                    if as_f64(value) < as_f64(current) {
+                        *current = value.clone();
+                    }
+                }
+                Aggregation::Max => {
+                    if as_f64(value) > as_f64(current) {
+                        *current = value.clone();
+                    }
+                }
+                Aggregation::Mean => {
+                    for key in ["sum", "count"] {
+                        if let (Some(total), Some(value)) = (current.get_mut(key), value.get(key)) {
+                            *total = add(total, value);
+                        }
+                    }
+                }
+                Aggregation::CountDistinct => {
+                    if let (Some(current), Some(values)) = (current.as_object_mut(), value.as_object()) {
+                        for value in values.keys() {
+                            if !current.contains_key(value) {
+                                current.insert(value.clone(), json!(true));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self, accumulated: &mut JsonObject) {
+        for (property, aggregation) in &self.fields {
+            let current = match accumulated.get_mut(property) {
+                Some(current) => current,
+                None => continue,
+            };
+            match aggregation {
+                Aggregation::Mean => {
+                    *current = json!(as_f64(&current["sum"]) / as_f64(&current["count"]));
+                }
+                Aggregation::CountDistinct => {
+                    *current = json!(current.as_object().map_or(0, |values| values.len()));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn as_f64(value: &Value) -> f64 {
+    value.as_f64().unwrap_or(f64::NAN)
+}
+
+/// Add two JSON numbers, staying integral while both are.
+fn add(a: &Value, b: &Value) -> Value {
+    match (a.as_i64(), b.as_i64()) {
+        (Some(a), Some(b)) if a.checked_add(b).is_some() => json!(a + b),
+        _ => json!(as_f64(a) + as_f64(b)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::cluster;
+
+    fn aggregations(property: &str, name: &str) -> Aggregations {
+        Aggregations::parse([(property.to_string(), name.to_string())]).unwrap()
+    }
+
+    #[test]
+    fn test_sum() {
+        let sum = aggregations("n", "sum");
+        let merged = cluster(&sum, &[&[json!({"n": 1}), json!({"n": 2})], &[json!({"n": 4}), json!({})]]);
+        assert_eq!(merged["n"], json!(7));
+
+        let merged = cluster(&sum, &[&[json!({"n": 1}), json!({"n": 0.5}), json!({"n": "x"})]]);
+        assert_eq!(merged["n"], json!(1.5));
+    }
+
+    #[test]
+    fn test_min() {
+        let min = aggregations("n", "min");
+        let merged = cluster(&min, &[&[json!({"n": 3}), json!({"n": -2.5})], &[json!({"n": 1}), json!({"n": null})]]);
+        assert_eq!(merged["n"], json!(-2.5));
+    }
+
+    #[test]
+    fn test_max() {
+        let max = aggregations("n", "max");
+        let merged = cluster(&max, &[&[json!({"n": 3}), json!({"n": -2.5})], &[json!({"n": 8}), json!({"n": true})]]);
+        assert_eq!(merged["n"], json!(8));
+    }
+
+    #[test]
+    fn test_mean() {
+        let mean = aggregations("n", "mean");
+        let merged = cluster(&mean, &[&[json!({"n": 1}), json!({"n": 2})], &[json!({"n": 6}), json!({"n": "x"})]]);
+        assert_eq!(merged["n"], json!(3.0));
+    }
+
+    #[test]
+    fn test_count() {
+        let count = aggregations("n", "count");
+        let merged = cluster(&count, &[&[json!({"n": 1}), json!({"n": "a"})], &[json!({"n": null}), json!({})]]);
+        assert_eq!(merged["n"], json!(2));
+    }
+
+    #[test]
+    fn test_count_distinct() {
+        let count_distinct = aggregations("n", "count_distinct");
+        let merged = cluster(
+            &count_distinct,
+            &[
+                &[json!({"n": "a"}), json!({"n": "b"}), json!({"n": "a"})],
+                &[json!({"n": "b"}), json!({"n": 1}), json!({"n": "1"}), json!({"n": null})],
+            ],
+        );
+        // Numbers and strings are distinct values
+        assert_eq!(merged["n"], json!(4));
+    }
+
+    #[test]
+    fn test_property_without_values() {
+        let sum = aggregations("n", "sum");
+        let merged = cluster(&sum, &[&[json!({"m": 1}), json!({"m": 2})]]);
+        assert!(!merged.contains_key("n"));
+    }
+
+    #[test]
+    fn test_unknown_aggregation() {
+        let err = Aggregations::parse([("n".to_string(), "median".to_string())]).unwrap_err();
+        assert_eq!(
+            err,
+            "Unknown aggregation 'median' for property 'n', expected one of sum, min, max, mean, count, count_distinct"
+        );
+    }
+
+    #[test]
+    fn test_reserved_property() {
+        for property in RESERVED {
+            let err = Aggregations::parse([(property.to_string(), "sum".to_string())]).unwrap_err();
+            assert_eq!(
+                err,
+                format!("Cannot aggregate property '{}', clusters already have a property of that name", property)
+            );
+        }
+    }
+}
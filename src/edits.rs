@@ -0,0 +1,404 @@
+//! Bookkeeping for `add`, `remove` and `update`. Edits go to a copy of the
+//! points of the index, which is reloaded from them when it is next queried.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use geojson::feature::Id;
+use geojson::Feature;
+use pyo3::prelude::*;
+
+use crate::supercluster::Supercluster;
+
+/// The point set as edited since the index was last built.
+pub struct Edits {
+    /// Bumped by every load and edit; the index is stale while the generation
+    /// it was built from is older.
+    pub generation: u64,
+
+    /// Property holding the id points are addressed by, the feature id if `None`.
+    id_property: Option<String>,
+
+    /// The index the edits apply to, set by the first edit after it was built.
+    base: Option<Arc<Supercluster>>,
+
+    /// Edited copy of the points of `base`, `None` for removed points until
+    /// the index is reloaded, so that the other points keep their position.
+    points: Vec<Option<Feature>>,
+
+    /// For every point in `points`, its index in `base` while it is unchanged.
+    origins: Vec<Option<usize>>,
+
+    /// Positions in `points` by point id.
+    positions: HashMap<String, usize>,
+}
+
+/// The edited points, to reload the index they were edited from.
+pub struct Pending {
+    /// The generation of the edits.
+    pub generation: u64,
+
+    /// The index the edits apply to.
+    pub base: Arc<Supercluster>,
+
+    /// The points, without the removed ones.
+    pub points: Vec<Feature>,
+
+    /// For every point, its index in `base` if it is unchanged.
+    pub origins: Vec<Option<usize>>,
+}
+
+impl Edits {
+    pub fn new(id_property: Option<String>) -> Self {
+        Edits {
+            generation: 0,
+            id_property,
+            base: None,
+            points: vec![],
+            origins: vec![],
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Forget all edits in favour of freshly loaded points, returning the
+    /// generation of the new index.
+    pub fn reset(&mut self) -> u64 {
+        self.clear();
+        self.generation += 1;
+        self.generation
+    }
+
+    /// Whether there were edits since the index was last built.
+    pub fn is_started(&self) -> bool {
+        self.base.is_some()
+    }
+
+    /// Start editing a copy of the points of `base`, the current index.
+    pub fn start(&mut self, base: Arc<Supercluster>) {
+        let points = base.points.to_vec();
+        self.positions = points
+            .iter()
+            .enumerate()
+            .filter_map(|(position, point)| Some((key_of(&self.id_property, point)?, position)))
+            .collect();
+        self.origins = (0..points.len()).map(Some).collect();
+        self.points = points.into_iter().map(Some).collect();
+        self.base = Some(base);
+    }
+
+    /// The edited points, if there were edits since the index was last built.
+    pub fn pending(&self) -> Option<Pending> {
+        let base = self.base.clone()?;
+        let (points, origins) = self
+            .points
+            .iter()
+            .zip(&self.origins)
+            .filter_map(|(point, &origin)| Some((point.clone()?, origin)))
+            .unzip();
+        Some(Pending {
+            generation: self.generation,
+            base,
+            points,
+            origins,
+        })
+    }
+
+    /// Drop the edits once the index reloaded from them is installed, unless
+    /// there were more edits since `generation`, which still apply to the
+    /// previous index.
+    pub fn rebase(&mut self, generation: u64) {
+        if self.generation == generation {
+            self.clear();
+        }
+    }
+
+    pub fn add(&mut self, features: Vec<Feature>) -> PyResult<()> {
+        assert!(self.is_started(), "edits not started");
+        let mut keys = Vec::with_capacity(features.len());
+        for feature in &features {
+            let key = key_of(&self.id_property, feature);
+            if let Some(key) = &key {
+                if self.positions.contains_key(key) || keys.contains(&Some(key.clone())) {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "A point with id {} already exists, use update to replace it",
+                        key
+                    )));
+                }
+            }
+            keys.push(key);
+        }
+        for (key, feature) in keys.into_iter().zip(features) {
+            if let Some(key) = key {
+                self.positions.insert(key, self.points.len());
+            }
+            self.points.push(Some(feature));
+            self.origins.push(None);
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, keys: &[String]) -> PyResult<()> {
+        for key in keys {
+            self.position(key)?;
+        }
+        for key in keys {
+            // None if listed twice
+            if let Some(position) = self.positions.remove(key) {
+                self.points[position] = None;
+                self.origins[position] = None;
+            }
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Replace the point with id `key`. A feature without an id of its own
+    /// takes over `id`, the id the point was addressed by.
+    pub fn update(&mut self, key: &str, id: serde_json::Value, mut feature: Feature) -> PyResult<()> {
+        let position = self.position(key)?;
+        match key_of(&self.id_property, &feature) {
+            Some(own_key) if own_key != key => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Feature has id {}, which does not match {}",
+                    own_key, key
+                )))
+            }
+            Some(_) => {}
+            None => match (&self.id_property, id) {
+                (Some(id_property), id) => {
+                    feature
+                        .properties
+                        .get_or_insert_with(Default::default)
+                        .insert(id_property.clone(), id);
+                }
+                (None, serde_json::Value::String(s)) => feature.id = Some(Id::String(s)),
+                (None, serde_json::Value::Number(n)) => feature.id = Some(Id::Number(n)),
+                (None, _) => {
+                    return Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(
+                        "Feature ids must be strings or numbers",
+                    ))
+                }
+            },
+        }
+        self.points[position] = Some(feature);
+        self.origins[position] = None;
+        self.generation += 1;
+        Ok(())
+    }
+
+    fn position(&self, key: &str) -> PyResult<usize> {
+        self.positions.get(key).copied().ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyKeyError, _>(format!("No point with id {}", key))
+        })
+    }
+
+    fn clear(&mut self) {
+        self.base = None;
+        self.points = vec![];
+        self.origins = vec![];
+        self.positions.clear();
+    }
+}
+
+/// The key a point is addressed by, if it has an id: the id serialized as
+/// JSON, so that `1` and `"1"` are different ids.
+fn key_of(id_property: &Option<String>, point: &Feature) -> Option<String> {
+    match id_property {
+        Some(id_property) => match point.property(id_property)? {
+            serde_json::Value::Null => None,
+            id => Some(id.to_string()),
+        },
+        None => match point.id.as_ref()? {
+            Id::String(s) => Some(serde_json::Value::String(s.clone()).to_string()),
+            Id::Number(n) => Some(n.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supercluster::Options;
+    use geojson::{Geometry, JsonObject, Value::Point};
+
+    const WORLD: [f64; 4] = [-180.0, -85.0, 180.0, 85.0];
+
+    fn point(id: u64, lng: f64, lat: f64) -> Feature {
+        Feature {
+            id: Some(Id::Number(id.into())),
+            geometry: Some(Geometry::new(Point(vec![lng, lat]))),
+            properties: Some(JsonObject::new()),
+            ..Default::default()
+        }
+    }
+
+    /// Points spread over a few degrees, so that they cluster at most zooms.
+    fn points(n: u64) -> Vec<Feature> {
+        let mut seed = 42_u64;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 11) as f64 / (1_u64 << 53) as f64
+        };
+        (0..n).map(|id| point(id, next() * 10.0, next() * 10.0)).collect()
+    }
+
+    fn index(points: Vec<Feature>) -> Arc<Supercluster> {
+        let mut index = Supercluster::new(Options::default());
+        index.load(points).unwrap();
+        Arc::new(index)
+    }
+
+    /// Reload the index the edits apply to from the edited points.
+    fn reload(edits: &Edits) -> Arc<Supercluster> {
+        let pending = edits.pending().unwrap();
+        let mut index = Supercluster::new(Options::default());
+        index.reload(&pending.base, pending.points, &pending.origins).unwrap();
+        Arc::new(index)
+    }
+
+    /// The clusters and points at every zoom, and the children of every cluster.
+    fn snapshot(index: &Supercluster) -> (Vec<Vec<Feature>>, Vec<Vec<Feature>>) {
+        let clusters: Vec<Vec<Feature>> = (0..=17).map(|zoom| index.get_clusters(WORLD, zoom)).collect();
+        let children = clusters
+            .iter()
+            .flatten()
+            .filter_map(|feature| feature.property("cluster_id")?.as_u64())
+            .map(|cluster_id| index.get_children(cluster_id as usize).unwrap())
+            .collect();
+        (clusters, children)
+    }
+
+    fn key(id: u64) -> String {
+        id.to_string()
+    }
+
+    #[test]
+    fn test_add_matches_load() {
+        let all = points(300);
+        let mut edits = Edits::new(None);
+        edits.start(index(all[..200].to_vec()));
+        edits.add(all[200..].to_vec()).unwrap();
+
+        assert_eq!(snapshot(&reload(&edits)), snapshot(&index(all)));
+    }
+
+    #[test]
+    fn test_add_rejects_duplicate_ids() {
+        let all = points(10);
+        let mut edits = Edits::new(None);
+        edits.start(index(all.clone()));
+
+        assert!(edits.add(vec![all[3].clone()]).is_err());
+        assert!(edits.add(vec![point(10, 0.0, 0.0), point(10, 1.0, 1.0)]).is_err());
+        assert_eq!(edits.pending().unwrap().points.len(), 10);
+    }
+
+    #[test]
+    fn test_remove_matches_load() {
+        let all = points(300);
+        let removed: Vec<u64> = (0..300).step_by(7).collect();
+        let mut edits = Edits::new(None);
+        edits.start(index(all.clone()));
+        edits.remove(&removed.iter().map(|&id| key(id)).collect::<Vec<_>>()).unwrap();
+
+        let remaining: Vec<Feature> = all
+            .into_iter()
+            .filter(|p| !matches!(&p.id, Some(Id::Number(id)) if removed.contains(&id.as_u64().unwrap())))
+            .collect();
+        assert_eq!(snapshot(&reload(&edits)), snapshot(&index(remaining)));
+    }
+
+    #[test]
+    fn test_remove_unknown_id_removes_nothing() {
+        let mut edits = Edits::new(None);
+        edits.start(index(points(10)));
+
+        assert!(edits.remove(&[key(1), key(99)]).is_err());
+        assert_eq!(edits.pending().unwrap().points.len(), 10);
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let mut edits = Edits::new(None);
+        edits.start(index(points(2)));
+        edits.remove(&[key(0), key(1)]).unwrap();
+
+        let index = reload(&edits);
+        assert!(index.points.is_empty());
+        assert!(index.get_clusters(WORLD, 0).is_empty());
+        assert!(index.get_tile(0, 0.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_update_matches_load() {
+        let mut all = points(300);
+        let mut edits = Edits::new(None);
+        edits.start(index(all.clone()));
+        for id in (0..300).step_by(11) {
+            let moved = point(id, 20.0 + id as f64 / 100.0, 20.0);
+            edits.update(&key(id), id.into(), moved.clone()).unwrap();
+            all[id as usize] = moved;
+        }
+
+        assert_eq!(snapshot(&reload(&edits)), snapshot(&index(all)));
+    }
+
+    #[test]
+    fn test_update_takes_over_id() {
+        let mut edits = Edits::new(None);
+        edits.start(index(points(3)));
+        let mut moved = point(0, 5.0, 5.0);
+        moved.id = None;
+        edits.update(&key(1), 1.into(), moved).unwrap();
+
+        assert_eq!(edits.pending().unwrap().points[1].id, Some(Id::Number(1.into())));
+        assert!(edits.update(&key(2), 2.into(), point(0, 5.0, 5.0)).is_err());
+        assert!(edits.update(&key(7), 7.into(), point(7, 5.0, 5.0)).is_err());
+    }
+
+    #[test]
+    fn test_removed_points_are_compacted() {
+        let all = points(300);
+        let mut edits = Edits::new(None);
+        edits.start(index(all.clone()));
+        edits.remove(&[key(0), key(150)]).unwrap();
+        edits.add(vec![point(300, 5.0, 5.0)]).unwrap();
+        edits.update(&key(42), 42.into(), point(42, 6.0, 6.0)).unwrap();
+
+        let mut expected = all;
+        expected[42] = point(42, 6.0, 6.0);
+        expected.remove(150);
+        expected.remove(0);
+        expected.push(point(300, 5.0, 5.0));
+        let index = reload(&edits);
+        assert_eq!(index.points.to_vec(), expected);
+        assert_eq!(snapshot(&index), snapshot(&self::index(expected)));
+    }
+
+    #[test]
+    fn test_edits_after_reload() {
+        let mut all = points(300);
+        let mut edits = Edits::new(None);
+        edits.start(index(all.clone()));
+        edits.remove(&[key(10)]).unwrap();
+        all.remove(10);
+        let pending = edits.pending().unwrap();
+
+        // Edits made while reloading still apply to the previous index
+        edits.add(vec![point(300, 3.0, 3.0)]).unwrap();
+        all.push(point(300, 3.0, 3.0));
+        edits.rebase(pending.generation);
+        assert_eq!(snapshot(&reload(&edits)), snapshot(&index(all.clone())));
+
+        let generation = edits.generation;
+        let reloaded = reload(&edits);
+        edits.rebase(generation);
+        assert!(edits.pending().is_none());
+
+        edits.start(reloaded);
+        edits.update(&key(20), 20.into(), point(20, 4.0, 4.0)).unwrap();
+        all[19] = point(20, 4.0, 4.0);
+        assert_eq!(snapshot(&reload(&edits)), snapshot(&index(all)));
+    }
+}
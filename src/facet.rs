@@ -0,0 +1,204 @@
+//! Per-cluster counts of the values of a categorical property, e.g. to draw
+//! clusters as pie charts, computed alongside any other cluster properties.
+
+use std::sync::Arc;
+
+use geojson::JsonObject;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::supercluster::ReduceError;
+use crate::supercluster::Reducer;
+
+/// Cluster property holding the counts by value.
+const FACETS: &str = "facets";
+
+/// Cluster property holding the number of points whose value was dropped from
+/// the counts by the cap on distinct values.
+const FACETS_OTHER: &str = "facets_other";
+
+/// Counts the string and integer values of `property` into `facets`, keeping
+/// the `limit` most frequent values of each cluster and adding up the others
+/// in `facets_other`. Clusters are merged with the counts of every value, which
+/// are only capped once they are finished, so that the kept counts are exact.
+#[derive(Debug)]
+pub struct Facets {
+    property: String,
+    limit: usize,
+
+    /// Reducer computing the other cluster properties, if any.
+    inner: Option<Arc<dyn Reducer>>,
+}
+
+impl Facets {
+    pub fn new(property: String, limit: usize, inner: Option<Arc<dyn Reducer>>) -> Self {
+        Facets { property, limit, inner }
+    }
+
+    /// The key a value is counted under, if it is categorical.
+    fn key(value: &Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) if n.is_i64() || n.is_u64() => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Drop the least frequent values beyond the cap, ties broken by value.
+    fn cap(&self, facets: &mut JsonObject, other: &mut u64) {
+        if facets.len() <= self.limit {
+            return;
+        }
+        let mut counts: Vec<(String, u64)> = std::mem::take(facets)
+            .into_iter()
+            .map(|(key, count)| (key, count.as_u64().unwrap_or(0)))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        *other += counts.drain(self.limit..).map(|(_, count)| count).sum::<u64>();
+        facets.extend(counts.into_iter().map(|(key, count)| (key, json!(count))));
+    }
+}
+
+/// Take the counts out of cluster properties, so that the inner reducer only
+/// sees its own properties.
+fn take_counts(properties: &mut JsonObject) -> (JsonObject, u64) {
+    let facets = match properties.remove(FACETS) {
+        Some(Value::Object(facets)) => facets,
+        _ => JsonObject::new(),
+    };
+    let other = properties.remove(FACETS_OTHER).and_then(|other| other.as_u64()).unwrap_or(0);
+    (facets, other)
+}
+
+fn put_counts(properties: &mut JsonObject, facets: JsonObject, other: u64) {
+    properties.insert(FACETS.to_string(), Value::Object(facets));
+    if other > 0 {
+        properties.insert(FACETS_OTHER.to_string(), json!(other));
+    }
+}
+
+impl Reducer for Facets {
+    fn map(&self, properties: &JsonObject) -> Result<JsonObject, ReduceError> {
+        let mut mapped = match &self.inner {
+            Some(inner) => inner.map(properties)?,
+            None => JsonObject::new(),
+        };
+        let mut facets = JsonObject::new();
+        if let Some(key) = properties.get(&self.property).and_then(Facets::key) {
+            facets.insert(key, json!(1));
+        }
+        put_counts(&mut mapped, facets, 0);
+        Ok(mapped)
+    }
+
+    fn reduce(&self, accumulated: &mut JsonObject, properties: &JsonObject) -> Result<(), ReduceError> {
+        let (mut facets, other) = take_counts(accumulated);
+        let mut properties = properties.clone();
+        let (merged, merged_other) = take_counts(&mut properties);
+        if let Some(inner) = &self.inner {
+            inner.reduce(accumulated, &properties)?;
+        }
+        for (key, count) in merged {
+            let count = count.as_u64().unwrap_or(0);
+            let current = facets.entry(key).or_insert(json!(0));
+            *current = json!(current.as_u64().unwrap_or(0) + count);
+        }
+        put_counts(accumulated, facets, other + merged_other);
+        Ok(())
+    }
+
+    fn finish(&self, accumulated: &mut JsonObject) {
+        let (mut facets, mut other) = take_counts(accumulated);
+        self.cap(&mut facets, &mut other);
+        if let Some(inner) = &self.inner {
+            inner.finish(accumulated);
+        }
+        put_counts(accumulated, facets, other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::Aggregations;
+    use crate::test_support::cluster;
+
+    fn kinds(kinds: &[&str]) -> Vec<Value> {
+        kinds.iter().map(|kind| json!({ "kind": kind })).collect()
+    }
+
+    #[test]
+    fn test_counts() {
+        let facets = Facets::new("kind".to_string(), 16, None);
+        let merged = cluster(&facets, &[&kinds(&["a", "b", "a"]), &[json!({"kind": "b"}), json!({}), json!({"kind": null})]]);
+        assert_eq!(merged, *json!({"facets": {"a": 2, "b": 2}}).as_object().unwrap());
+    }
+
+    #[test]
+    fn test_facet_limit() {
+        let facets = Facets::new("kind".to_string(), 2, None);
+        let merged = cluster(&facets, &[&kinds(&["c", "c", "a"]), &kinds(&["a", "c", "b"])]);
+        assert_eq!(merged["facets"], json!({"a": 2, "c": 3}));
+        assert_eq!(merged["facets_other"], json!(1));
+
+        // Ties are broken by value
+        let merged = cluster(&facets, &[&kinds(&["a", "b", "c"]), &kinds(&["c", "c"])]);
+        assert_eq!(merged["facets"], json!({"a": 1, "c": 3}));
+        assert_eq!(merged["facets_other"], json!(1));
+    }
+
+    #[test]
+    fn test_facet_limit_applies_to_merged_counts() {
+        // The value kept is never the most frequent one of a child
+        let facets = Facets::new("kind".to_string(), 1, None);
+        let children = [kinds(&["a", "a", "x"]), kinds(&["b", "b", "x"]), kinds(&["c", "c", "x"]), kinds(&["d", "d", "x"])];
+        let merged = cluster(&facets, &children.iter().map(Vec::as_slice).collect::<Vec<_>>());
+        assert_eq!(merged["facets"], json!({"x": 4}));
+        assert_eq!(merged["facets_other"], json!(8));
+
+        // Equally frequent values keep their counts
+        let facets = Facets::new("kind".to_string(), 2, None);
+        let points: Vec<Value> = (0..100).map(|i| ["a", "b", "c"][i % 3]).map(|kind| json!({ "kind": kind })).collect();
+        let merged = cluster(&facets, &points.chunks(7).collect::<Vec<_>>());
+        assert_eq!(merged["facets"], json!({"a": 34, "b": 33}));
+        assert_eq!(merged["facets_other"], json!(33));
+    }
+
+    #[test]
+    fn test_facets_other_only_when_capped() {
+        let facets = Facets::new("kind".to_string(), 2, None);
+        let merged = cluster(&facets, &[&kinds(&["a", "b"]), &kinds(&["a"])]);
+        assert!(!merged.contains_key("facets_other"));
+
+        let facets = Facets::new("kind".to_string(), 0, None);
+        let merged = cluster(&facets, &[&kinds(&["a", "b"]), &kinds(&["a"])]);
+        assert_eq!(merged["facets"], json!({}));
+        assert_eq!(merged["facets_other"], json!(3));
+    }
+
+    #[test]
+    fn test_int_and_string_values() {
+        let facets = Facets::new("kind".to_string(), 16, None);
+        let merged = cluster(
+            &facets,
+            &[
+                &[json!({"kind": 1}), json!({"kind": "1"}), json!({"kind": -2})],
+                &[json!({"kind": 1.5}), json!({"kind": true}), json!({"kind": ["a"]}), json!({"kind": u64::MAX})],
+            ],
+        );
+        // Integers are counted under their decimal form, together with equal strings, other
+        // values are not counted
+        assert_eq!(merged["facets"], json!({"1": 2, "-2": 1, "18446744073709551615": 1}));
+    }
+
+    #[test]
+    fn test_inner_reducer() {
+        let mean = Aggregations::parse([("n".to_string(), "mean".to_string())]).unwrap();
+        let facets = Facets::new("kind".to_string(), 16, Some(Arc::new(mean)));
+        let merged = cluster(
+            &facets,
+            &[&[json!({"kind": "a", "n": 1}), json!({"kind": "b", "n": 2})], &[json!({"kind": "a", "n": 6})]],
+        );
+        assert_eq!(merged, *json!({"facets": {"a": 2, "b": 1}, "n": 3.0}).as_object().unwrap());
+    }
+}
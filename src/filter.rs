@@ -0,0 +1,273 @@
+//! Filter expressions selecting the points a filtered query clusters, e.g.
+//! `["all", ["==", "open", true], [">=", "severity", 3]]`, evaluated against
+//! columns holding the values of the filterable properties.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+
+use geojson::Feature;
+use serde_json::Value;
+
+const SYNTAX: &str = "expected [\"all\" | \"any\", filter, ...], [\"!\", filter], \
+    [\"has\" | \"!has\", property], [\"in\" | \"!in\", property, [value, ...]] \
+    or [\"==\" | \"!=\" | \"<\" | \"<=\" | \">\" | \">=\", property, value]";
+
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A parsed filter expression, with properties resolved to their column.
+#[derive(Debug)]
+pub enum Filter {
+    All(Vec<Filter>),
+    Any(Vec<Filter>),
+    Not(Box<Filter>),
+    Has(usize),
+    In(usize, Vec<Value>),
+    Compare(usize, Op, Value),
+}
+
+impl Filter {
+    /// Parse a filter expression over the `filterable` properties.
+    pub fn parse(expr: &Value, filterable: &[String]) -> Result<Self, String> {
+        let invalid = || format!("Invalid filter {}, {}", expr, SYNTAX);
+        let items = expr.as_array().filter(|items| !items.is_empty()).ok_or_else(invalid)?;
+        let name = items[0].as_str().ok_or_else(invalid)?;
+        let args = &items[1..];
+
+        let column = |arg: Option<&Value>| {
+            let property = arg.and_then(|p| p.as_str()).ok_or_else(invalid)?;
+            filterable.iter().position(|f| f == property).ok_or_else(|| {
+                format!(
+                    "Property '{}' is not filterable, declare it in filterable",
+                    property
+                )
+            })
+        };
+        let filters = |args: &[Value]| {
+            args.iter()
+                .map(|arg| Filter::parse(arg, filterable))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        let filter = match (name, args.len()) {
+            ("all", _) => Filter::All(filters(args)?),
+            ("any", _) => Filter::Any(filters(args)?),
+            ("!", 1) => Filter::Not(Box::new(Filter::parse(&args[0], filterable)?)),
+            ("has", 1) => Filter::Has(column(args.first())?),
+            ("!has", 1) => Filter::Not(Box::new(Filter::Has(column(args.first())?))),
+            ("in" | "!in", 2) => {
+                let values = args[1].as_array().ok_or_else(invalid)?.clone();
+                let filter = Filter::In(column(args.first())?, values);
+                match name {
+                    "in" => filter,
+                    _ => Filter::Not(Box::new(filter)),
+                }
+            }
+            (_, 2) => {
+                let op = match name {
+                    "==" => Op::Eq,
+                    "!=" => Op::Ne,
+                    "<" => Op::Lt,
+                    "<=" => Op::Le,
+                    ">" => Op::Gt,
+                    ">=" => Op::Ge,
+                    _ => return Err(invalid()),
+                };
+                Filter::Compare(column(args.first())?, op, args[1].clone())
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(filter)
+    }
+
+    /// Whether point `i` matches.
+    pub fn matches(&self, columns: &Columns, i: usize) -> bool {
+        match self {
+            Filter::All(filters) => filters.iter().all(|f| f.matches(columns, i)),
+            Filter::Any(filters) => filters.iter().any(|f| f.matches(columns, i)),
+            Filter::Not(filter) => !filter.matches(columns, i),
+            Filter::Has(column) => !columns.get(*column, i).is_null(),
+            Filter::In(column, values) => {
+                let value = columns.get(*column, i);
+                values.iter().any(|v| compare(value, v) == Some(Ordering::Equal))
+            }
+            Filter::Compare(column, op, operand) => {
+                let ordering = compare(columns.get(*column, i), operand);
+                match op {
+                    Op::Eq => ordering == Some(Ordering::Equal),
+                    // Like the other comparisons, but true for points without the property
+                    Op::Ne => ordering != Some(Ordering::Equal),
+                    Op::Lt => ordering == Some(Ordering::Less),
+                    Op::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    Op::Gt => ordering == Some(Ordering::Greater),
+                    Op::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                }
+            }
+        }
+    }
+}
+
+/// Compare a property value with an operand: numbers numerically, strings
+/// lexicographically, anything else only for equality. Missing and null
+/// values compare with nothing.
+fn compare(value: &Value, operand: &Value) -> Option<Ordering> {
+    match (value, operand) {
+        (Value::Null, _) => None,
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (a, b) if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+/// The values of the filterable properties of every point, one column per
+/// property, so that filters are evaluated without going through the points.
+#[derive(Debug)]
+pub struct Columns {
+    columns: Vec<Vec<Value>>,
+}
+
+impl Columns {
+    pub fn new<P: Borrow<Feature>>(filterable: &[String], points: impl IntoIterator<Item = P>) -> Self {
+        let mut columns = vec![Vec::new(); filterable.len()];
+        for point in points {
+            let point = point.borrow();
+            for (column, property) in columns.iter_mut().zip(filterable) {
+                column.push(point.property(property).cloned().unwrap_or(Value::Null));
+            }
+        }
+        Columns { columns }
+    }
+
+    fn get(&self, column: usize, i: usize) -> &Value {
+        &self.columns[column][i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geojson::JsonObject;
+    use serde_json::json;
+
+    fn filterable() -> Vec<String> {
+        ["open", "severity", "name"].iter().map(|p| p.to_string()).collect()
+    }
+
+    fn columns(points: &[Value]) -> Columns {
+        let points = points.iter().map(|properties| Feature {
+            properties: properties.as_object().cloned().or(Some(JsonObject::new())),
+            ..Default::default()
+        });
+        Columns::new(&filterable(), points)
+    }
+
+    /// The indexes of the points matching `expr`.
+    fn matching(expr: Value, points: &[Value]) -> Vec<usize> {
+        let filter = Filter::parse(&expr, &filterable()).unwrap();
+        let columns = columns(points);
+        (0..points.len()).filter(|&i| filter.matches(&columns, i)).collect()
+    }
+
+    fn points() -> Vec<Value> {
+        vec![
+            json!({"open": true, "severity": 3, "name": "b"}),
+            json!({"open": false, "severity": 1.5, "name": "a"}),
+            json!({"open": true, "severity": "high", "name": "c"}),
+            json!({"severity": null}),
+            json!({"open": "true", "severity": 5, "name": 1}),
+        ]
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for expr in [
+            json!([]),
+            json!("=="),
+            json!({"==": ["open", true]}),
+            json!([1, "open", true]),
+            json!(["~=", "open", true]),
+            json!(["==", "open"]),
+            json!(["==", 1, true]),
+            json!(["!", ["has", "open"], ["has", "name"]]),
+            json!(["in", "name", "a"]),
+        ] {
+            let err = Filter::parse(&expr, &filterable()).unwrap_err();
+            assert!(err.starts_with("Invalid filter ") && err.ends_with(SYNTAX), "{}", err);
+        }
+
+        // Errors point at the invalid part of a nested expression
+        let err = Filter::parse(&json!(["all", ["has", "open"], ["nope"]]), &filterable()).unwrap_err();
+        assert!(err.starts_with("Invalid filter [\"nope\"], expected"), "{}", err);
+    }
+
+    #[test]
+    fn test_rejects_properties_not_filterable() {
+        for expr in [json!(["==", "kind", "a"]), json!(["any", ["has", "open"], ["!has", "kind"]])] {
+            assert_eq!(
+                Filter::parse(&expr, &filterable()).unwrap_err(),
+                "Property 'kind' is not filterable, declare it in filterable"
+            );
+        }
+    }
+
+    #[test]
+    fn test_compare_numbers() {
+        assert_eq!(matching(json!(["==", "severity", 3]), &points()), vec![0]);
+        assert_eq!(matching(json!(["==", "severity", 3.0]), &points()), vec![0]);
+        assert_eq!(matching(json!(["<", "severity", 3]), &points()), vec![1]);
+        assert_eq!(matching(json!(["<=", "severity", 3]), &points()), vec![0, 1]);
+        assert_eq!(matching(json!([">", "severity", 1.5]), &points()), vec![0, 4]);
+        assert_eq!(matching(json!([">=", "severity", 1.5]), &points()), vec![0, 1, 4]);
+        // Points without a comparable value only match !=
+        assert_eq!(matching(json!(["!=", "severity", 3]), &points()), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_compare_strings() {
+        assert_eq!(matching(json!(["==", "name", "a"]), &points()), vec![1]);
+        assert_eq!(matching(json!([">", "name", "a"]), &points()), vec![0, 2]);
+        assert_eq!(matching(json!(["<=", "name", "b"]), &points()), vec![0, 1]);
+        // Strings and numbers never compare
+        assert_eq!(matching(json!(["==", "name", "1"]), &points()), Vec::<usize>::new());
+        assert_eq!(matching(json!([">=", "severity", "high"]), &points()), vec![2]);
+    }
+
+    #[test]
+    fn test_compare_bools() {
+        assert_eq!(matching(json!(["==", "open", true]), &points()), vec![0, 2]);
+        assert_eq!(matching(json!(["==", "open", false]), &points()), vec![1]);
+        assert_eq!(matching(json!(["!=", "open", true]), &points()), vec![1, 3, 4]);
+        // Bools only compare for equality
+        assert_eq!(matching(json!([">", "open", false]), &points()), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_has_and_in() {
+        assert_eq!(matching(json!(["has", "open"]), &points()), vec![0, 1, 2, 4]);
+        assert_eq!(matching(json!(["!has", "severity"]), &points()), vec![3]);
+        assert_eq!(matching(json!(["in", "name", ["a", "c", 1]]), &points()), vec![1, 2, 4]);
+        assert_eq!(matching(json!(["!in", "severity", [3, 5]]), &points()), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_combinators() {
+        let open = json!(["==", "open", true]);
+        let severe = json!([">=", "severity", 3]);
+
+        assert_eq!(matching(json!(["all", open, severe]), &points()), vec![0]);
+        assert_eq!(matching(json!(["any", open, severe]), &points()), vec![0, 2, 4]);
+        assert_eq!(matching(json!(["!", ["any", open, severe]]), &points()), vec![1, 3]);
+        assert_eq!(matching(json!(["all", ["!", open], ["any", ["has", "name"], severe]]), &points()), vec![1, 4]);
+        // Empty combinations match everything and nothing
+        assert_eq!(matching(json!(["all"]), &points()), vec![0, 1, 2, 3, 4]);
+        assert_eq!(matching(json!(["any"]), &points()), Vec::<usize>::new());
+    }
+}
@@ -1,22 +1,286 @@
+// pyo3 0.20 macros trip this lint on recent toolchains
+#![allow(non_local_definitions)]
+
+mod aggregate;
+mod edits;
+mod facet;
+mod filter;
+mod mvt;
+mod py_reducer;
+mod supercluster;
+#[cfg(test)]
+mod test_support;
+
+use aggregate::Aggregations;
+use edits::Edits;
+use facet::Facets;
+use filter::Columns;
+use filter::Filter;
+use geojson::feature::Id;
 use geojson::Feature;
+use geojson::FeatureCollection;
 use geojson::Geometry;
 use geojson::JsonObject;
 use geojson::Value::Point;
+use numpy::IntoPyArray;
+use numpy::PyReadonlyArray1;
+use py_reducer::PyReducer;
 use pyo3::prelude::*;
+use pyo3::types::PyBool;
+use pyo3::types::PyBytes;
 use pyo3::types::PyDict;
+use pyo3::types::PyFloat;
 use pyo3::types::PyList;
+use pyo3::types::PyLong;
+use pyo3::types::PyString;
+use pyo3::types::PyTuple;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::sync::RwLock;
+use supercluster::Anchor;
+use supercluster::CoordinateSystem;
 use supercluster::Options;
+use supercluster::ReduceError;
+use supercluster::Reducer;
+use supercluster::Selection;
 use supercluster::Supercluster;
+use supercluster::MAX_ZOOM;
+
+/// What `load` does with features it cannot convert.
+enum OnError {
+    /// Abort the load with the first error.
+    Raise,
+    /// Leave invalid features out of the index.
+    Skip,
+    /// Leave invalid features out and return `(index, exception)` pairs.
+    Collect,
+}
 
-#[pyclass]
+impl OnError {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "raise" => Ok(OnError::Raise),
+            "skip" => Ok(OnError::Skip),
+            "collect" => Ok(OnError::Collect),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "on_error must be 'raise', 'skip' or 'collect', got '{}'",
+                value
+            ))),
+        }
+    }
+}
+
+/// Point clustering index.
+///
+/// The index is immutable once built: `load*` methods build a new index with
+/// the GIL released and then swap it in, and every query runs against the
+/// index that was current when it started, also with the GIL released. All
+/// methods are therefore safe to call concurrently from several threads,
+/// including loading while queries are in flight.
+///
+/// `add`, `remove` and `update` address points by their GeoJSON feature id,
+/// or by the `id_property` property if one is given. Edits are recorded on a
+/// copy of the points, which costs O(n) for the first edit after the index was
+/// built and O(k) for an edit of k points after that. The first query that
+/// follows reclusters the edited points: clusters away from the added, removed
+/// and moved points are taken over from the previous index, without searching
+/// for their points or calling `map`/`reduce` again, so the cost mostly
+/// depends on how many clusters the edits touch, although every zoom level
+/// is still reindexed in O(n log n). Batch edits between queries to pay for a
+/// single reclustering. Results, cluster ids included, are those of loading
+/// the edited points from scratch: removed points are dropped, and the point
+/// indexes of the points after them shift down.
+///
+/// Queries take an optional `filter` expression over the properties listed in
+/// `filterable`, e.g. `["all", ["==", "open", True], [">=", "severity", 3]]`,
+/// and then only count the matching points. Expressions are lists of an
+/// operator and its arguments: `"all"`/`"any"` with filters, `"!"` with a
+/// filter, `"has"`/`"!has"` with a property, `"in"`/`"!in"` with a property
+/// and a list of values, or a comparison `"=="`, `"!="`, `"<"`, `"<="`, `">"`,
+/// `">="` with a property and a value. Filtered queries reuse the clusters of
+/// the index rather than clustering the matching points again: a cluster
+/// stands for its matching points only, with their number, center, weight and
+/// `map`/`reduce` properties, clusters without matching points are left out,
+/// and those with a single one are that point. The first query with a filter
+/// reduces every cluster to its matching points, in one pass over the zoom
+/// levels without any spatial search, which is cached for the following
+/// queries with the same filter until the points change. The 8 most recently used filters are kept, each with a match flag
+/// per point and a record per cluster with matching points, along with the
+/// values of the `filterable` properties of every point. Cluster ids and point
+/// indexes are the same with or without a filter.
+///
+/// With a `facet_property`, clusters also carry `facets`, the number of their
+/// points by value of that property, for string and integer values. Only the
+/// `facet_limit` most frequent values are kept, and the points with the other
+/// values are counted in `facets_other`.
+///
+/// `radius` can vary by zoom with a `radius_schedule`, either a dict of radii
+/// by the zoom they apply from, e.g. `{0: 80, 10: 40}`, or a list of radii
+/// for zooms 0, 1, ... whose last radius applies to the zooms beyond it.
+/// Points with a numeric `radius_property` are clustered with that radius at
+/// every zoom instead, and clusters with the largest radius of their points,
+/// where points without one count with the radius of the zoom they merge at.
+/// A cluster grows from one point or cluster by absorbing its neighbours
+/// within its radius. Radii are in pixels and have to be positive numbers.
+/// Tiles take in the clusters and points up to the radius of their zoom, or
+/// the largest radius of the points if that is larger, outside their edges.
+///
+/// With `coordinate_system="cartesian"`, coordinates are planar, e.g. pixels
+/// or meters on a floor plan, rather than longitudes and latitudes: they are
+/// clustered as they are, bounding boxes are `[min_x, min_y, max_x, max_y]`,
+/// do not wrap and have to overlap the bounds, and `get_clusters_arrays`
+/// returns x and y as `lon` and `lat`. The required `bounds`, `[min_x, min_y,
+/// max_x, max_y]`, set the world that tile 0/0/0 covers, with y pointing up;
+/// non-square bounds are extended down or to the right to a square. Points
+/// outside the bounds are clustered too, but not part of any tile.
+///
+/// Features have to be points unless `geometry_anchor` is set, in which case
+/// other geometries are clustered at a point standing in for them: their
+/// `"centroid"`, or a `"representative"` point which is always on the
+/// geometry, e.g. inside a U-shaped parcel where its centroid is not. Leaves
+/// keep their original geometry, except in tiles where they are that point.
+#[pyclass(module = "pysupercluster")]
 struct PySupercluster {
-    inner: Supercluster,
+    inner: RwLock<Built>,
+    edits: Mutex<Edits>,
+    filtered: Mutex<FilterCache>,
+    options: Options,
+    reducer: ReducerSpec,
+    settings: Settings,
+}
+
+/// Options of the bindings rather than the clustering engine, kept to
+/// serialize an index.
+#[derive(Clone)]
+struct Settings {
+    id_property: Option<String>,
+    filterable: Vec<String>,
+    facet_property: Option<String>,
+    facet_limit: usize,
+}
+
+/// Default cap on the distinct values counted in `facets`.
+const FACET_LIMIT: usize = 16;
+
+impl Settings {
+    fn from_meta(meta: &JsonObject) -> Self {
+        let extra = meta.get("extra");
+        Settings {
+            id_property: extra
+                .and_then(|extra| extra.get("id_property"))
+                .and_then(|id_property| id_property.as_str())
+                .map(|id_property| id_property.to_string()),
+            filterable: extra
+                .and_then(|extra| extra.get("filterable"))
+                .and_then(|filterable| serde_json::from_value(filterable.clone()).ok())
+                .unwrap_or_default(),
+            facet_property: extra
+                .and_then(|extra| extra.get("facet_property"))
+                .and_then(|facet_property| facet_property.as_str())
+                .map(|facet_property| facet_property.to_string()),
+            facet_limit: extra
+                .and_then(|extra| extra.get("facet_limit"))
+                .and_then(|facet_limit| facet_limit.as_u64())
+                .map_or(FACET_LIMIT, |facet_limit| facet_limit as usize),
+        }
+    }
+}
+
+/// Number of the next temporary file written by `save`, which is unique in
+/// the process.
+static SAVES: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of filter selections kept for reuse.
+const FILTER_CACHE_SIZE: usize = 8;
+
+/// The points matching filters, which are only valid for the index they were
+/// selected from.
+#[derive(Default)]
+struct FilterCache {
+    base: Option<Arc<Supercluster>>,
+    columns: Option<Arc<Columns>>,
+    /// Most recently used first.
+    filtered: Vec<Filtered>,
+}
+
+/// The selection of the points matching a filter.
+struct Filtered {
+    key: String,
+    selection: Arc<Selection>,
+}
+
+/// How cluster properties are computed, kept to serialize an index along with
+/// what it takes to recreate its reducer.
+#[derive(Clone)]
+enum ReducerSpec {
+    None,
+    Aggregations(HashMap<String, String>),
+    Callables { map: Option<PyObject>, reduce: PyObject },
+}
+
+impl ReducerSpec {
+    /// Recreate the reducer of a serialized index from its metadata and the
+    /// callables passed again.
+    fn from_meta(meta: &JsonObject, map: Option<PyObject>, reduce: Option<PyObject>) -> PyResult<Self> {
+        let invalid = PyErr::new::<pyo3::exceptions::PyValueError, _>;
+        let extra = meta.get("extra").and_then(|extra| extra.as_object());
+        let aggregations = extra.and_then(|extra| extra.get("aggregations"));
+        let callables = extra.and_then(|extra| extra.get("callables")).and_then(|c| c.as_bool());
+        let reducer = match (aggregations, callables, map, reduce) {
+            (Some(aggregations), _, None, None) => ReducerSpec::Aggregations(
+                serde_json::from_value(aggregations.clone()).map_err(|err| invalid(err.to_string()))?,
+            ),
+            (None, Some(true), map, Some(reduce)) => ReducerSpec::Callables { map, reduce },
+            (None, Some(true), _, None) => {
+                return Err(invalid("The index was built with map and reduce, which have to be passed again".to_string()))
+            }
+            (None, _, None, None) => ReducerSpec::None,
+            _ => return Err(invalid("The index was built without map and reduce".to_string())),
+        };
+        Ok(reducer)
+    }
+
+    /// The reducer computing cluster properties, counting facets too if
+    /// `settings` ask for them.
+    fn reducer(&self, settings: &Settings) -> PyResult<Option<Arc<dyn Reducer>>> {
+        let reducer: Option<Arc<dyn Reducer>> = match self {
+            ReducerSpec::None => None,
+            ReducerSpec::Aggregations(aggregations) => Some(Arc::new(
+                Aggregations::parse(aggregations.clone())
+                    .map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?,
+            )),
+            ReducerSpec::Callables { map, reduce } => {
+                Some(Arc::new(PyReducer::new(map.clone(), reduce.clone())))
+            }
+        };
+        Ok(match &settings.facet_property {
+            Some(facet_property) => Some(Arc::new(Facets::new(
+                facet_property.clone(),
+                settings.facet_limit,
+                reducer,
+            ))),
+            None => reducer,
+        })
+    }
+}
+
+/// An index together with the generation of the points it was built from.
+#[derive(Clone)]
+struct Built {
+    generation: u64,
+    index: Arc<Supercluster>,
 }
 
 #[pymethods]
 impl PySupercluster {
     #[new]
-    #[pyo3(signature = (min_zoom=0, max_zoom=16, min_points=2, radius=40.0, extent=512.0, node_size=64))]
+    #[pyo3(signature = (min_zoom=0, max_zoom=16, min_points=2, radius=40.0, extent=512.0, node_size=64, aggregations=None, map=None, reduce=None, weight_property=None, id_property=None, stable_ids=false, filterable=None, facet_property=None, facet_limit=FACET_LIMIT, radius_schedule=None, radius_property=None, coordinate_system="geographic", bounds=None, geometry_anchor=None))]
+    #[allow(clippy::too_many_arguments)]
     fn new(
         min_zoom: u8,
         max_zoom: u8,
@@ -24,7 +288,66 @@ impl PySupercluster {
         radius: f64,
         extent: f64,
         node_size: usize,
-    ) -> Self {
+        aggregations: Option<HashMap<String, String>>,
+        map: Option<&PyAny>,
+        reduce: Option<&PyAny>,
+        weight_property: Option<String>,
+        id_property: Option<String>,
+        stable_ids: bool,
+        filterable: Option<Vec<String>>,
+        facet_property: Option<String>,
+        facet_limit: usize,
+        radius_schedule: Option<&PyAny>,
+        radius_property: Option<String>,
+        coordinate_system: &str,
+        bounds: Option<[f64; 4]>,
+        geometry_anchor: Option<&str>,
+    ) -> PyResult<Self> {
+        for (name, callable) in [("map", map), ("reduce", reduce)] {
+            if callable.is_some_and(|c| !c.is_callable()) {
+                return Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                    "{} must be callable",
+                    name
+                )));
+            }
+        }
+        let reducer = match (aggregations, map, reduce) {
+            (None, None, None) => ReducerSpec::None,
+            (Some(aggregations), None, None) => ReducerSpec::Aggregations(aggregations),
+            (None, map, Some(reduce)) => ReducerSpec::Callables {
+                map: map.map(|map| map.into()),
+                reduce: reduce.into(),
+            },
+            (None, Some(_), None) => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                    "map has no effect without reduce",
+                ))
+            }
+            (Some(_), _, _) => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                    "aggregations cannot be combined with map and reduce",
+                ))
+            }
+        };
+        if max_zoom > MAX_ZOOM {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "max_zoom must be at most {}, got {}",
+                MAX_ZOOM, max_zoom
+            )));
+        }
+        check_radius("radius", radius)?;
+        let radius_schedule = match radius_schedule {
+            Some(schedule) => parse_radius_schedule(schedule)?,
+            None => vec![],
+        };
+        let coordinate_system = parse_coordinate_system(coordinate_system, bounds)?;
+        let geometry_anchor = geometry_anchor.map(parse_geometry_anchor).transpose()?;
+        let settings = Settings {
+            id_property,
+            filterable: filterable.unwrap_or_default(),
+            facet_property,
+            facet_limit,
+        };
         let options = Options {
             min_zoom,
             max_zoom,
@@ -32,96 +355,628 @@ impl PySupercluster {
             radius,
             extent,
             node_size,
+            reducer: reducer.reducer(&settings)?,
+            weight_property,
+            stable_ids,
+            radius_schedule,
+            radius_property,
+            coordinate_system,
+            geometry_anchor,
         };
-        PySupercluster {
-            inner: Supercluster::new(options),
+        Ok(PySupercluster::with_index(Supercluster::new(options), reducer, settings))
+    }
+
+    /// Deserialize an index written by `to_bytes`. Indexes built with `map`
+    /// and `reduce` need them passed again.
+    #[staticmethod]
+    #[pyo3(signature = (data, map=None, reduce=None))]
+    fn from_bytes(py: Python, data: &[u8], map: Option<PyObject>, reduce: Option<PyObject>) -> PyResult<Self> {
+        let meta = Supercluster::read_meta(data).map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
+        let reducer = ReducerSpec::from_meta(&meta, map, reduce)?;
+        let settings = Settings::from_meta(&meta);
+        let options_reducer = reducer.reducer(&settings)?;
+        let index = py
+            .allow_threads(|| Supercluster::from_bytes(data, options_reducer))
+            .map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
+        Ok(PySupercluster::with_index(index, reducer, settings))
+    }
+
+    /// Serialize the index, including pending edits, for `from_bytes`.
+    fn to_bytes(&self, py: Python) -> PyResult<PyObject> {
+        let index = self.index(py)?;
+        let extra = self.extra_meta();
+        let bytes = py.allow_threads(|| index.to_bytes(&extra));
+        Ok(PyBytes::new(py, &bytes).to_object(py))
+    }
+
+    /// Write the index to `path` for `open_mmap`. The file is replaced rather
+    /// than overwritten, so processes that have it open keep a consistent view.
+    fn save(&self, py: Python, path: PathBuf) -> PyResult<()> {
+        let index = self.index(py)?;
+        let extra = self.extra_meta();
+        py.allow_threads(|| {
+            // Concurrent saves to the same path each write their own file
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(format!(".{}.{}.tmp", std::process::id(), SAVES.fetch_add(1, Ordering::Relaxed)));
+            let saved = std::fs::write(&tmp, index.to_bytes(&extra)).and_then(|()| std::fs::rename(&tmp, &path));
+            if saved.is_err() {
+                let _ = std::fs::remove_file(&tmp);
+            }
+            saved
+        })?;
+        Ok(())
+    }
+
+    /// Open an index written by `save` without reading it into memory: the
+    /// file is mapped read-only and queried in place, so processes opening the
+    /// same file share one copy through the page cache. Edits and loads build
+    /// a private index as usual.
+    #[staticmethod]
+    #[pyo3(signature = (path, map=None, reduce=None))]
+    fn open_mmap(py: Python, path: PathBuf, map: Option<PyObject>, reduce: Option<PyObject>) -> PyResult<Self> {
+        let file = std::fs::File::open(&path)?;
+        // SAFETY: the map is read-only, and `save` replaces files instead of
+        // writing to them. Like any map it must not be truncated while open.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        let meta = Supercluster::read_meta(&mmap).map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
+        let reducer = ReducerSpec::from_meta(&meta, map, reduce)?;
+        let settings = Settings::from_meta(&meta);
+        let options_reducer = reducer.reducer(&settings)?;
+        let index = py
+            .allow_threads(|| Supercluster::from_mapped(Arc::new(mmap), options_reducer))
+            .map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
+        Ok(PySupercluster::with_index(index, reducer, settings))
+    }
+
+    fn __getstate__(&self, py: Python) -> PyResult<PyObject> {
+        let (map, reduce) = match &self.reducer {
+            ReducerSpec::Callables { map, reduce } => (map.clone(), Some(reduce.clone())),
+            _ => (None, None),
+        };
+        Ok((self.to_bytes(py)?, map, reduce).to_object(py))
+    }
+
+    fn __setstate__(&mut self, py: Python, state: (&[u8], Option<PyObject>, Option<PyObject>)) -> PyResult<()> {
+        let (data, map, reduce) = state;
+        *self = PySupercluster::from_bytes(py, data, map, reduce)?;
+        Ok(())
+    }
+
+    #[pyo3(signature = (points, on_error="raise"))]
+    fn load(&self, py: Python, points: Vec<&PyAny>, on_error: &str) -> PyResult<Option<Vec<(usize, PyObject)>>> {
+        let on_error = OnError::parse(on_error)?;
+        let results = points
+            .into_iter()
+            .enumerate()
+            .map(|(index, point)| self.convert_feature(index, point));
+        self.load_results(py, results, on_error)
+    }
+
+    #[pyo3(signature = (s, on_error="raise"))]
+    fn load_geojson_str(&self, py: Python, s: &str, on_error: &str) -> PyResult<Option<Vec<(usize, PyObject)>>> {
+        let on_error = OnError::parse(on_error)?;
+        let anchor = self.options.geometry_anchor;
+        let results = py.allow_threads(|| features_from_geojson(s, anchor))?;
+        self.load_results(py, results, on_error)
+    }
+
+    #[pyo3(signature = (path, on_error="raise"))]
+    fn load_geojson_file(&self, py: Python, path: PathBuf, on_error: &str) -> PyResult<Option<Vec<(usize, PyObject)>>> {
+        let on_error = OnError::parse(on_error)?;
+        let anchor = self.options.geometry_anchor;
+        let results = py.allow_threads(|| {
+            let s = std::fs::read_to_string(&path)?;
+            features_from_geojson(&s, anchor)
+        })?;
+        self.load_results(py, results, on_error)
+    }
+
+    #[pyo3(signature = (lons, lats, ids=None))]
+    fn load_numpy(
+        &self,
+        py: Python,
+        lons: PyReadonlyArray1<f64>,
+        lats: PyReadonlyArray1<f64>,
+        ids: Option<PyReadonlyArray1<i64>>,
+    ) -> PyResult<()> {
+        let lons = lons.as_array();
+        let lats = lats.as_array();
+        let ids = ids.as_ref().map(|ids| ids.as_array());
+
+        if lats.len() != lons.len() || ids.is_some_and(|ids| ids.len() != lons.len()) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "lons, lats and ids must have the same length",
+            ));
+        }
+
+        // The points are built straight from the arrays, without the GIL
+        let features = py.allow_threads(|| {
+            lons.iter()
+                .zip(lats.iter())
+                .enumerate()
+                .map(|(index, (&longitude, &latitude))| {
+                    if !longitude.is_finite() || !latitude.is_finite() {
+                        return Err(format!(
+                            "Coordinates of point {} must be finite, got [{}, {}]",
+                            index, longitude, latitude
+                        ));
+                    }
+                    // Points loaded from arrays have no properties, which queries treat as empty
+                    Ok(Feature {
+                        geometry: Some(Geometry::new(Point(vec![longitude, latitude]))),
+                        properties: None,
+                        id: ids.map(|ids| Id::Number(ids[index].into())),
+                        ..Default::default()
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        });
+        let features = features.map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
+
+        self.replace_index(py, features)
+    }
+
+    #[pyo3(signature = (bbox, zoom, filter=None))]
+    fn get_clusters(&self, py: Python, bbox: [f64;4], zoom: u8, filter: Option<&PyAny>) -> PyResult<Vec<PyObject>> {
+        check_bbox(bbox, self.options.coordinate_system)?;
+        let (inner, selection) = self.query_index(py, filter)?;
+        let clusters = py.allow_threads(|| inner.with_selection(selection.as_deref()).get_clusters(bbox, zoom));
+        let mut py_clusters = Vec::new();
+        for cluster in clusters {
+            py_clusters.push(feature_to_pyobject(py, &cluster)?);
+        }
+        Ok(py_clusters)
+    }
+
+    #[pyo3(signature = (bbox, zoom, as_bytes=false, filter=None))]
+    fn get_clusters_json(&self, py: Python, bbox: [f64; 4], zoom: u8, as_bytes: bool, filter: Option<&PyAny>) -> PyResult<PyObject> {
+        check_bbox(bbox, self.options.coordinate_system)?;
+        let (inner, selection) = self.query_index(py, filter)?;
+        let json = py.allow_threads(|| {
+            let mut features = inner.with_selection(selection.as_deref()).get_clusters(bbox, zoom);
+            // Match `get_clusters`, which leaves the stringified cluster id out
+            // and gives points loaded without properties empty ones
+            for feature in &mut features {
+                if feature.contains_property("cluster") {
+                    feature.id = None;
+                }
+                feature.properties.get_or_insert_with(JsonObject::new);
+            }
+            FeatureCollection {
+                bbox: None,
+                features,
+                foreign_members: None,
+            }
+            .to_string()
+        });
+        Ok(if as_bytes {
+            PyBytes::new(py, json.as_bytes()).to_object(py)
+        } else {
+            json.to_object(py)
+        })
+    }
+
+    #[pyo3(signature = (bbox, zoom, filter=None))]
+    fn get_clusters_arrays(&self, py: Python, bbox: [f64; 4], zoom: u8, filter: Option<&PyAny>) -> PyResult<PyObject> {
+        check_bbox(bbox, self.options.coordinate_system)?;
+        let (inner, selection) = self.query_index(py, filter)?;
+        let clusters = py.allow_threads(|| inner.with_selection(selection.as_deref()).get_cluster_points(bbox, zoom));
+
+        let arrays = PyDict::new(py);
+        arrays.set_item("lon", clusters.iter().map(|c| c.lng).collect::<Vec<_>>().into_pyarray(py))?;
+        arrays.set_item("lat", clusters.iter().map(|c| c.lat).collect::<Vec<_>>().into_pyarray(py))?;
+        arrays.set_item(
+            "is_cluster",
+            clusters.iter().map(|c| c.cluster_id.is_some()).collect::<Vec<_>>().into_pyarray(py),
+        )?;
+        arrays.set_item(
+            "cluster_id",
+            clusters
+                .iter()
+                .map(|c| c.cluster_id.map_or(-1, |id| id as i64))
+                .collect::<Vec<_>>()
+                .into_pyarray(py),
+        )?;
+        arrays.set_item(
+            "point_count",
+            clusters.iter().map(|c| c.point_count as i64).collect::<Vec<_>>().into_pyarray(py),
+        )?;
+        arrays.set_item(
+            "point_index",
+            clusters
+                .iter()
+                .map(|c| c.point_index.map_or(-1, |index| index as i64))
+                .collect::<Vec<_>>()
+                .into_pyarray(py),
+        )?;
+        Ok(arrays.to_object(py))
+    }
+
+    #[pyo3(signature = (cluster_id, limit=10, offset=0, filter=None))]
+    fn get_leaves(&self, py: Python, cluster_id: usize, limit: usize, offset: usize, filter: Option<&PyAny>) -> PyResult<Vec<PyObject>> {
+        let (inner, selection) = self.query_index(py, filter)?;
+        check_cluster_id(&inner, cluster_id)?;
+        let leaves = py.allow_threads(|| inner.with_selection(selection.as_deref()).get_leaves(cluster_id, limit, offset));
+        let mut py_leaves = Vec::new();
+        for leaf in leaves {
+            py_leaves.push(feature_to_pyobject(py, &leaf)?);
+        }
+        Ok(py_leaves)
+    }
+
+    #[pyo3(signature = (cluster_id, filter=None))]
+    fn get_children(&self, py: Python, cluster_id: usize, filter: Option<&PyAny>) -> PyResult<Vec<PyObject>> {
+        let (inner, selection) = self.query_index(py, filter)?;
+        check_cluster_id(&inner, cluster_id)?;
+        let children = py
+            .allow_threads(|| inner.with_selection(selection.as_deref()).get_children(cluster_id))
+            .map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
+        let mut py_children = Vec::new();
+        for child in children {
+            py_children.push(feature_to_pyobject(py, &child)?);
         }
+        Ok(py_children)
+    }
+
+    #[pyo3(signature = (z, x, y, filter=None))]
+    fn get_tile(&self, py: Python, z: u8, x: u32, y: u32, filter: Option<&PyAny>) -> PyResult<Option<PyObject>> {
+        check_tile(z, x, y)?;
+        let (inner, selection) = self.query_index(py, filter)?;
+        let tile = match py.allow_threads(|| inner.with_selection(selection.as_deref()).get_tile(z, x as f64, y as f64)) {
+            Some(tile) => tile,
+            None => return Ok(None),
+        };
+        let py_features = PyList::empty(py);
+        for feature in &tile.features {
+            py_features.append(tile_feature_to_pyobject(py, feature)?)?;
+        }
+        let py_tile = PyDict::new(py);
+        py_tile.set_item("type", "FeatureCollection")?;
+        py_tile.set_item("features", py_features)?;
+        Ok(Some(py_tile.to_object(py)))
+    }
+
+    #[pyo3(signature = (z, x, y, layer_name="clusters", filter=None))]
+    fn get_tile_mvt(&self, py: Python, z: u8, x: u32, y: u32, layer_name: &str, filter: Option<&PyAny>) -> PyResult<PyObject> {
+        check_tile(z, x, y)?;
+        let (inner, selection) = self.query_index(py, filter)?;
+        let extent = self.options.extent as u32;
+        let encoded = py
+            .allow_threads(|| {
+                let features = match inner.with_selection(selection.as_deref()).get_tile(z, x as f64, y as f64) {
+                    Some(tile) => tile.features,
+                    None => vec![],
+                };
+                mvt::encode_tile(layer_name, extent, &features)
+            })
+            .map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
+        Ok(PyBytes::new(py, &encoded).to_object(py))
+    }
+
+    #[pyo3(signature = (features, on_error="raise"))]
+    fn add(&self, py: Python, features: Vec<&PyAny>, on_error: &str) -> PyResult<Option<Vec<(usize, PyObject)>>> {
+        let on_error = OnError::parse(on_error)?;
+        let results = features
+            .into_iter()
+            .enumerate()
+            .map(|(index, feature)| self.convert_feature(index, feature));
+        let (features, errors) = self.convert_results(py, results, on_error)?;
+        self.edit(|edits| edits.add(features))?;
+        Ok(errors)
     }
 
-    #[pyo3(signature = (points))]
-    fn load(&mut self, _py: Python, points: Vec<&PyDict>) -> PyResult<()> {
-        let features: Vec<Feature> = points
+    #[pyo3(signature = (ids))]
+    fn remove(&self, ids: Vec<&PyAny>) -> PyResult<()> {
+        let keys = ids
             .into_iter()
-            .map(|p| {
-                let geometry = p
-                    .get_item("geometry")
-                    .expect("Geometry not found")
-                    .unwrap()
-                    .extract::<&PyDict>()?;
-                let properties = p
-                    .get_item("properties")
-                    .expect("Properties not found")
-                    .unwrap()
-                    .extract::<&PyDict>()?;
-                let coords = geometry
-                    .get_item("coordinates")
-                    .expect("Coordinates not found")
-                    .unwrap()
-                    .extract::<&PyList>()?;
-
-                let latitude = coords.get_item(1).unwrap().extract::<f64>().unwrap();
-                let longitude = coords.get_item(0).unwrap().extract::<f64>().unwrap();     
-
-                //hacky way to convert PyDict to json string
-                let json_properities = properties.to_string().replace("'", "\"");
-                    
-                Ok(Feature {
-                    geometry: Some(Geometry::new(Point(vec![longitude, latitude]))),                                        
-                    properties: Some(
-                        serde_json::from_str(&json_properities)
-                            .unwrap_or_else(|_| JsonObject::new()),
-                    ),
+            .map(|id| Ok(id_to_json(id)?.to_string()))
+            .collect::<PyResult<Vec<_>>>()?;
+        self.edit(|edits| edits.remove(&keys))
+    }
+
+    #[pyo3(signature = (id, feature))]
+    fn update(&self, id: &PyAny, feature: &PyAny) -> PyResult<()> {
+        let id = id_to_json(id)?;
+        let feature = self.check_properties(0, self.convert_feature(0, feature)?)?;
+        self.edit(|edits| edits.update(&id.to_string(), id.clone(), feature))
+    }
+
+    /// The zoom level a cluster was formed at, which is the highest zoom it is
+    /// returned at, and the index of the loaded point it originated from.
+    #[pyo3(signature = (cluster_id))]
+    fn decode_cluster_id(&self, py: Python, cluster_id: usize) -> PyResult<(u8, usize)> {
+        let inner = self.index(py)?;
+        check_cluster_id(&inner, cluster_id)
+    }
+
+    #[pyo3(signature = (cluster_id, filter=None))]
+    fn get_cluster_expansion_zoom(&self, py: Python, cluster_id: usize, filter: Option<&PyAny>) -> PyResult<usize> {
+        let (inner, selection) = self.query_index(py, filter)?;
+        check_cluster_id(&inner, cluster_id)?;
+        let expansion_zoom =
+            py.allow_threads(|| inner.with_selection(selection.as_deref()).get_cluster_expansion_zoom(cluster_id));
+        Ok(expansion_zoom)
+    }
+}
+
+impl PySupercluster {
+    fn with_index(index: Supercluster, reducer: ReducerSpec, settings: Settings) -> Self {
+        PySupercluster {
+            options: index.options().clone(),
+            inner: RwLock::new(Built {
+                generation: 0,
+                index: Arc::new(index),
+            }),
+            edits: Mutex::new(Edits::new(settings.id_property.clone())),
+            filtered: Mutex::new(FilterCache::default()),
+            reducer,
+            settings,
+        }
+    }
+
+    /// Metadata stored with a serialized index to recreate this instance.
+    fn extra_meta(&self) -> JsonObject {
+        let mut extra = JsonObject::new();
+        match &self.reducer {
+            ReducerSpec::None => {}
+            ReducerSpec::Aggregations(aggregations) => {
+                extra.insert("aggregations".to_string(), serde_json::json!(aggregations));
+            }
+            ReducerSpec::Callables { .. } => {
+                extra.insert("callables".to_string(), serde_json::Value::Bool(true));
+            }
+        }
+        if let Some(id_property) = &self.settings.id_property {
+            extra.insert("id_property".to_string(), id_property.clone().into());
+        }
+        if !self.settings.filterable.is_empty() {
+            extra.insert("filterable".to_string(), serde_json::json!(self.settings.filterable));
+        }
+        if let Some(facet_property) = &self.settings.facet_property {
+            extra.insert("facet_property".to_string(), facet_property.clone().into());
+            extra.insert("facet_limit".to_string(), self.settings.facet_limit.into());
+        }
+        extra
+    }
+
+    fn built(&self) -> Built {
+        self.inner
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn edits(&self) -> std::sync::MutexGuard<'_, Edits> {
+        self.edits.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn filter_cache(&self) -> std::sync::MutexGuard<'_, FilterCache> {
+        self.filtered.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The index a query with `filter` runs against, along with the selection
+    /// of the points matching the filter.
+    fn query_index(&self, py: Python, filter: Option<&PyAny>) -> PyResult<(Arc<Supercluster>, Option<Arc<Selection>>)> {
+        let index = self.index(py)?;
+        let filter = match filter {
+            Some(filter) if !filter.is_none() => filter,
+            _ => return Ok((index, None)),
+        };
+        let expr = pyobject_to_json(filter).map_err(|unsupported| {
+            PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                "Unsupported type '{}' in filter",
+                type_name(unsupported)
+            ))
+        })?;
+        let parsed = Filter::parse(&expr, &self.settings.filterable)
+            .map_err(PyErr::new::<pyo3::exceptions::PyValueError, _>)?;
+        let key = expr.to_string();
+        let is_base = |cache: &FilterCache| cache.base.as_ref().is_some_and(|base| Arc::ptr_eq(base, &index));
+
+        let columns = {
+            let mut cache = self.filter_cache();
+            if !is_base(&cache) {
+                *cache = FilterCache {
+                    base: Some(index.clone()),
                     ..Default::default()
-                })
+                };
+            }
+            if let Some(hit) = cache.filtered.iter().position(|filtered| filtered.key == key) {
+                let filtered = cache.filtered.remove(hit);
+                let selection = filtered.selection.clone();
+                cache.filtered.insert(0, filtered);
+                return Ok((index, Some(selection)));
+            }
+            cache.columns.clone()
+        };
+        let columns = match columns {
+            Some(columns) => columns,
+            None => {
+                let filterable = &self.settings.filterable;
+                let points = (0..index.points.len()).map(|i| index.points.get(i));
+                let columns = Arc::new(py.allow_threads(|| Columns::new(filterable, points)));
+                let mut cache = self.filter_cache();
+                if is_base(&cache) {
+                    cache.columns = Some(columns.clone());
+                }
+                columns
+            }
+        };
+
+        let selection = py.allow_threads(|| {
+            let selected = (0..index.points.len()).map(|i| parsed.matches(&columns, i)).collect();
+            index.select(selected).map(Arc::new)
+        });
+        let selection = selection.map_err(reduce_error)?;
+
+        let mut cache = self.filter_cache();
+        if is_base(&cache) {
+            cache.filtered.insert(
+                0,
+                Filtered {
+                    key,
+                    selection: selection.clone(),
+                },
+            );
+            cache.filtered.truncate(FILTER_CACHE_SIZE);
+        }
+        Ok((index, Some(selection)))
+    }
+
+    /// The index queries should run against, first reloading it if points
+    /// were edited since it was built.
+    fn index(&self, py: Python) -> PyResult<Arc<Supercluster>> {
+        let built = self.built();
+        let pending = {
+            let edits = self.edits();
+            if !edits.is_started() || edits.generation <= built.generation {
+                return Ok(built.index);
+            }
+            edits.pending().expect("edits started")
+        };
+        let options = self.options.clone();
+        let index = py
+            .allow_threads(|| {
+                let mut inner = Supercluster::new(options);
+                inner.reload(&pending.base, pending.points, &pending.origins)?;
+                Ok(Arc::new(inner))
             })
-            .collect::<PyResult<Vec<Feature>>>()?;
+            .map_err(reduce_error)?;
+        self.install(pending.generation, index.clone());
+        self.edits().rebase(pending.generation);
+        Ok(index)
+    }
 
-        self.inner.load(features);
+    /// Build an index over `features` without holding the GIL.
+    fn build(&self, py: Python, features: Vec<Feature>) -> PyResult<Arc<Supercluster>> {
+        let options = self.options.clone();
+        let inner = py.allow_threads(|| {
+            let mut inner = Supercluster::new(options);
+            inner.load(features)?;
+            Ok(Arc::new(inner))
+        });
+        inner.map_err(reduce_error)
+    }
 
+    /// Make `index` the current one unless a newer one was installed while
+    /// it was being built.
+    fn install(&self, generation: u64, index: Arc<Supercluster>) {
+        let mut built = self.inner.write().unwrap_or_else(PoisonError::into_inner);
+        if generation > built.generation {
+            *built = Built { generation, index };
+        }
+    }
+
+    /// Build an index over `features` and make it the current one, dropping
+    /// any edits. Errors raised by `map`/`reduce` leave the current index in
+    /// place.
+    fn replace_index(&self, py: Python, features: Vec<Feature>) -> PyResult<()> {
+        let index = self.build(py, features)?;
+        let generation = self.edits().reset();
+        self.install(generation, index);
         Ok(())
     }
 
-    
-    #[pyo3(signature = (bbox, zoom))]
-    fn get_clusters(&self, py: Python, bbox: [f64;4], zoom: u8) -> PyResult<Vec<PyObject>> {
-        let clusters = self.inner.get_clusters(bbox, zoom);
-        let mut py_clusters = Vec::new();
-        for cluster in clusters {
-            let py_cluster = PyDict::new(py);
-            if let Some(geometry) = &cluster.geometry {
-                let geometry_dict = PyDict::new(py);
-                geometry_dict.set_item("type", "Point")?;
+    /// Apply an edit to the points, starting from those of the current index
+    /// if this is the first edit since a load.
+    fn edit(&self, edit: impl FnOnce(&mut Edits) -> PyResult<()>) -> PyResult<()> {
+        let mut edits = self.edits();
+        if !edits.is_started() {
+            edits.start(self.built().index);
+        }
+        edit(&mut edits)
+    }
 
-                match &geometry.value {
-                    geojson::Value::Point(coords) => {
-                        geometry_dict.set_item("coordinates", coords)?;
-                    },
-                    _ => return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>("Expected point geometry")),
+    /// Convert a feature dict, rejecting a weight or radius that is NaN or
+    /// infinite before it is turned into null like in any other property.
+    fn convert_feature(&self, index: usize, feature: &PyAny) -> PyResult<Feature> {
+        let converted = feature_from_pyobject(index, feature, self.options.geometry_anchor)?;
+        let properties = feature.get_item("properties").ok().filter(|properties| !properties.is_none());
+        let names = [("Weight", &self.options.weight_property), ("Radius", &self.options.radius_property)];
+        for (name, property) in names {
+            let value = match (properties, property) {
+                (Some(properties), Some(property)) => properties.get_item(property.as_str()).ok(),
+                _ => None,
+            };
+            if let Some(value) = value.and_then(|value| value.downcast::<PyFloat>().ok()) {
+                if !value.value().is_finite() {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "{} of feature {} must be a positive number, got {}",
+                        name,
+                        index,
+                        value.value()
+                    )));
                 }
-                
-                py_cluster.set_item("geometry", geometry_dict)?;
             }
+        }
+        Ok(converted)
+    }
 
-            if let Some(properties) = &cluster.properties {
-                let properties_dict = PyDict::new(py);
-                for (key, value) in properties {
-                    let py_value = json_to_pyobject(py, value);
-                    properties_dict.set_item(key, py_value)?;
+    /// Reject features whose weight or radius property is set to something
+    /// other than a positive number; features without a weight weigh 1, and
+    /// features without a radius use the radius of the zoom level.
+    fn check_properties(&self, index: usize, feature: Feature) -> PyResult<Feature> {
+        let properties = [("Weight", &self.options.weight_property), ("Radius", &self.options.radius_property)];
+        for (name, property) in properties {
+            let value = match property {
+                Some(property) => feature.property(property),
+                None => None,
+            };
+            match value {
+                None | Some(serde_json::Value::Null) => {}
+                Some(v) if v.as_f64().is_some_and(|v| v.is_finite() && v > 0.0) => {}
+                Some(v) => {
+                    return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                        "{} of feature {} must be a positive number, got {}",
+                        name, index, v
+                    )))
                 }
-                py_cluster.set_item("properties", properties_dict)?;
-            } else {
-                py_cluster.set_item("properties", PyDict::new(py))?;
             }
-
-            py_cluster.set_item("type", "Feature")?;
-            py_clusters.push(py_cluster.to_object(py));
         }
-        Ok(py_clusters)
+        Ok(feature)
     }
 
-    fn get_cluster_expansion_zoom(&self, cluster_id: usize) -> PyResult<usize> {
-        let expansion_zoom = self.inner.get_cluster_expansion_zoom(cluster_id);
-        Ok(expansion_zoom)
+    /// Load the successfully converted features, handling failed ones as
+    /// requested by `on_error`.
+    fn load_results(
+        &self,
+        py: Python,
+        results: impl IntoIterator<Item = PyResult<Feature>>,
+        on_error: OnError,
+    ) -> PyResult<Option<Vec<(usize, PyObject)>>> {
+        let (features, errors) = self.convert_results(py, results, on_error)?;
+        self.replace_index(py, features)?;
+        Ok(errors)
+    }
+
+    /// Split converted features from failed ones, which are handled as
+    /// requested by `on_error`.
+    #[allow(clippy::type_complexity)]
+    fn convert_results(
+        &self,
+        py: Python,
+        results: impl IntoIterator<Item = PyResult<Feature>>,
+        on_error: OnError,
+    ) -> PyResult<(Vec<Feature>, Option<Vec<(usize, PyObject)>>)> {
+        let mut features = Vec::new();
+        let mut errors = Vec::new();
+
+        for (index, result) in results.into_iter().enumerate() {
+            match result.and_then(|feature| self.check_properties(index, feature)) {
+                Ok(feature) => features.push(feature),
+                Err(err) => match on_error {
+                    OnError::Raise => return Err(err),
+                    OnError::Skip => {}
+                    OnError::Collect => errors.push((index, err.into_py(py))),
+                },
+            }
+        }
+
+        let errors = match on_error {
+            OnError::Collect => Some(errors),
+            _ => None,
+        };
+        Ok((features, errors))
     }
 }
 
@@ -131,6 +986,432 @@ fn pysupercluster(_py: Python, m: &PyModule) -> PyResult<()> {
     Ok(())
 }
 
+/// Convert a GeoJSON-style point feature dict into a `Feature`, reporting
+/// problems against the feature's position in the input. Other geometries are
+/// accepted with an `anchor` to cluster them at.
+fn feature_from_pyobject(index: usize, point: &PyAny, anchor: Option<Anchor>) -> PyResult<Feature> {
+    let point = point.downcast::<PyDict>().map_err(|_| {
+        PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+            "Feature {} is a '{}', expected a dict",
+            index,
+            type_name(point)
+        ))
+    })?;
+
+    let geometry = match point.get_item("geometry")? {
+        Some(geometry) if !geometry.is_none() => geometry,
+        _ => {
+            return Err(PyErr::new::<pyo3::exceptions::PyKeyError, _>(format!(
+                "Feature {} has no geometry",
+                index
+            )))
+        }
+    };
+    let geometry = geometry.downcast::<PyDict>().map_err(|_| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Geometry of feature {} is a '{}', expected a dict",
+            index,
+            type_name(geometry)
+        ))
+    })?;
+
+    if let Some(geometry_type) = geometry.get_item("type")? {
+        if !geometry_type.eq("Point")? {
+            let anchor = anchor.ok_or_else(|| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Geometry of feature {} has type {}, expected 'Point', or set geometry_anchor",
+                    index,
+                    geometry_type.repr().map(|r| r.to_string()).unwrap_or_default()
+                ))
+            })?;
+            let json = pyobject_to_json(geometry).map_err(|unsupported| {
+                PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                    "Unsupported type '{}' in geometry of feature {}",
+                    type_name(unsupported),
+                    index
+                ))
+            })?;
+            let geometry = Geometry::from_json_value(json).map_err(|err| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Geometry of feature {} is not valid GeoJSON: {}",
+                    index, err
+                ))
+            })?;
+            check_anchor(index, &geometry, anchor)?;
+            let feature = Feature {
+                geometry: Some(geometry),
+                ..Default::default()
+            };
+            return properties_from_pyobject(index, point, feature);
+        }
+    }
+
+    let coords = geometry.get_item("coordinates")?.ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyKeyError, _>(format!(
+            "Geometry of feature {} has no coordinates",
+            index
+        ))
+    })?;
+    let coords = coords
+        .extract::<Vec<f64>>()
+        .ok()
+        .filter(|c| c.len() >= 2 && c[0].is_finite() && c[1].is_finite())
+        .ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Coordinates of feature {} must be a numeric [longitude, latitude] pair, got {}",
+                index,
+                coords.repr().map(|r| r.to_string()).unwrap_or_default()
+            ))
+        })?;
+    let longitude = coords[0];
+    let latitude = coords[1];
+
+    let feature = Feature {
+        geometry: Some(Geometry::new(Point(vec![longitude, latitude]))),
+        ..Default::default()
+    };
+    properties_from_pyobject(index, point, feature)
+}
+
+/// Complete a feature converted by `feature_from_pyobject` with the id and
+/// properties of the feature dict.
+fn properties_from_pyobject(index: usize, point: &PyDict, feature: Feature) -> PyResult<Feature> {
+    let mut json_properties = JsonObject::new();
+    if let Some(properties) = point.get_item("properties")? {
+        if !properties.is_none() {
+            let properties = properties.downcast::<PyDict>().map_err(|_| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Properties of feature {} are a '{}', expected a dict",
+                    index,
+                    type_name(properties)
+                ))
+            })?;
+            for (key, value) in properties {
+                let key = key.extract::<String>().map_err(|_| {
+                    PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                        "Property key {} of feature {} is not a string",
+                        key.repr().map(|r| r.to_string()).unwrap_or_default(),
+                        index
+                    ))
+                })?;
+                let json_value = pyobject_to_json(value).map_err(|unsupported| {
+                    PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                        "Unsupported type '{}' in property '{}' of feature {}",
+                        type_name(unsupported),
+                        key,
+                        index
+                    ))
+                })?;
+                json_properties.insert(key, json_value);
+            }
+        }
+    }
+
+    let id = match point.get_item("id")? {
+        Some(id) if !id.is_none() => Some(id_from_pyobject(index, id)?),
+        _ => None,
+    };
+
+    Ok(Feature {
+        properties: Some(json_properties),
+        id,
+        ..feature
+    })
+}
+
+/// Check that a non-point geometry has a point to cluster it at.
+fn check_anchor(index: usize, geometry: &Geometry, anchor: Anchor) -> PyResult<()> {
+    if anchor.point(&geometry.value).is_none() {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Coordinates of feature {} must be non-empty numeric [longitude, latitude] pairs",
+            index
+        )));
+    }
+    Ok(())
+}
+
+fn id_from_pyobject(index: usize, id: &PyAny) -> PyResult<Id> {
+    if let Ok(s) = id.downcast::<PyString>() {
+        return Ok(Id::String(s.to_string_lossy().into_owned()));
+    }
+    match pyobject_to_json(id) {
+        Ok(serde_json::Value::Number(n)) => Ok(Id::Number(n)),
+        _ => Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+            "Id of feature {} is a '{}', expected a str or number",
+            index,
+            type_name(id)
+        ))),
+    }
+}
+
+/// A point id given to `remove` or `update`, as the JSON value it is
+/// compared with.
+fn id_to_json(id: &PyAny) -> PyResult<serde_json::Value> {
+    match pyobject_to_json(id) {
+        Ok(id @ (serde_json::Value::String(_) | serde_json::Value::Number(_))) => Ok(id),
+        _ => Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+            "Point ids must be a str or number, got '{}'",
+            type_name(id)
+        ))),
+    }
+}
+
+/// Parse a FeatureCollection or a bare array of features. Malformed JSON fails
+/// as a whole, while each feature is converted and checked on its own.
+fn features_from_geojson(s: &str, anchor: Option<Anchor>) -> PyResult<Vec<PyResult<Feature>>> {
+    let invalid = |msg: String| PyErr::new::<pyo3::exceptions::PyValueError, _>(msg);
+    let value: serde_json::Value =
+        serde_json::from_str(s).map_err(|err| invalid(format!("Invalid GeoJSON: {}", err)))?;
+
+    let items = match value {
+        serde_json::Value::Array(items) => items,
+        serde_json::Value::Object(mut object) => match object.get("type").and_then(|t| t.as_str()) {
+            Some("FeatureCollection") => match object.remove("features") {
+                Some(serde_json::Value::Array(items)) => items,
+                _ => return Err(invalid("FeatureCollection has no features array".to_string())),
+            },
+            Some("Feature") => vec![serde_json::Value::Object(object)],
+            _ => return Err(invalid("Expected a FeatureCollection or an array of features".to_string())),
+        },
+        _ => return Err(invalid("Expected a FeatureCollection or an array of features".to_string())),
+    };
+
+    Ok(items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let feature = Feature::from_json_value(item)
+                .map_err(|err| invalid(format!("Feature {} is not valid GeoJSON: {}", index, err)))?;
+            check_feature(index, feature, anchor)
+        })
+        .collect())
+}
+
+/// Apply the checks `feature_from_pyobject` does to a feature parsed on the
+/// Rust side.
+fn check_feature(index: usize, mut feature: Feature, anchor: Option<Anchor>) -> PyResult<Feature> {
+    let coords = match feature.geometry.as_ref().map(|g| &g.value) {
+        Some(Point(coords)) => coords,
+        Some(other) => {
+            let anchor = anchor.ok_or_else(|| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Geometry of feature {} has type '{}', expected 'Point', or set geometry_anchor",
+                    index,
+                    other.type_name()
+                ))
+            })?;
+            check_anchor(index, feature.geometry.as_ref().unwrap(), anchor)?;
+            if feature.properties.is_none() {
+                feature.properties = Some(JsonObject::new());
+            }
+            return Ok(feature);
+        }
+        None => {
+            return Err(PyErr::new::<pyo3::exceptions::PyKeyError, _>(format!(
+                "Feature {} has no geometry",
+                index
+            )))
+        }
+    };
+
+    if coords.len() < 2 || !coords[0].is_finite() || !coords[1].is_finite() {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Coordinates of feature {} must be a numeric [longitude, latitude] pair, got {:?}",
+            index, coords
+        )));
+    }
+
+    if feature.properties.is_none() {
+        feature.properties = Some(JsonObject::new());
+    }
+
+    Ok(feature)
+}
+
+/// Reject ids that are not those of a cluster of this index, e.g. ids of
+/// another index, before they reach supercluster, which would underflow or
+/// return unrelated clusters on them. Returns the decoded id.
+fn check_cluster_id(inner: &Supercluster, cluster_id: usize) -> PyResult<(u8, usize)> {
+    inner.decode_cluster_id(cluster_id).ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "No cluster with the specified id: {}",
+            cluster_id
+        ))
+    })
+}
+
+fn parse_geometry_anchor(name: &str) -> PyResult<Anchor> {
+    match name {
+        "centroid" => Ok(Anchor::Centroid),
+        "representative" => Ok(Anchor::Representative),
+        _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "geometry_anchor must be 'centroid' or 'representative', got '{}'",
+            name
+        ))),
+    }
+}
+
+fn parse_coordinate_system(name: &str, bounds: Option<[f64; 4]>) -> PyResult<CoordinateSystem> {
+    let invalid = PyErr::new::<pyo3::exceptions::PyValueError, _>;
+    match (name, bounds) {
+        ("geographic", None) => Ok(CoordinateSystem::Geographic),
+        ("geographic", Some(_)) => Err(invalid("bounds only apply to the cartesian coordinate system".to_string())),
+        ("cartesian", Some(bounds)) => {
+            if !bounds.iter().all(|c| c.is_finite()) || bounds[0] >= bounds[2] || bounds[1] >= bounds[3] {
+                return Err(invalid(format!(
+                    "Invalid bounds: {:?}, expected [min_x, min_y, max_x, max_y]",
+                    bounds
+                )));
+            }
+            Ok(CoordinateSystem::Cartesian { bounds })
+        }
+        ("cartesian", None) => Err(invalid("The cartesian coordinate system requires bounds".to_string())),
+        _ => Err(invalid(format!(
+            "coordinate_system must be 'geographic' or 'cartesian', got '{}'",
+            name
+        ))),
+    }
+}
+
+/// Radii by the zoom they apply from, sorted by zoom, from a dict by zoom or a
+/// list with one radius per zoom from 0.
+fn parse_radius_schedule(schedule: &PyAny) -> PyResult<Vec<(u8, f64)>> {
+    let mut schedule: Vec<(u8, f64)> = if let Ok(by_zoom) = schedule.downcast::<PyDict>() {
+        by_zoom.extract::<HashMap<u8, f64>>()?.into_iter().collect()
+    } else if let Ok(radii) = schedule.extract::<Vec<f64>>() {
+        if radii.len() > u8::MAX as usize + 1 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "radius_schedule has more radii than zoom levels",
+            ));
+        }
+        radii.into_iter().enumerate().map(|(zoom, radius)| (zoom as u8, radius)).collect()
+    } else {
+        return Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(
+            "radius_schedule must be a dict of radii by zoom or a list of radii",
+        ));
+    };
+    schedule.sort_by_key(|&(zoom, _)| zoom);
+    for &(zoom, radius) in &schedule {
+        check_radius(&format!("radius_schedule radius of zoom {}", zoom), radius)?;
+    }
+    Ok(schedule)
+}
+
+/// Radii are distances in pixels, so they have to be positive numbers.
+fn check_radius(name: &str, radius: f64) -> PyResult<()> {
+    if !(radius.is_finite() && radius > 0.0) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "{} must be a positive number, got {}",
+            name, radius
+        )));
+    }
+    Ok(())
+}
+
+/// The error of a failed load: the one raised by `map`/`reduce` if they are
+/// Python callables.
+fn reduce_error(err: ReduceError) -> PyErr {
+    match err.downcast::<PyErr>() {
+        Ok(err) => *err,
+        Err(err) => PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(err.to_string()),
+    }
+}
+
+/// Bounding boxes may cross the antimeridian and extend past the poles, but
+/// must be made of numbers. Cartesian ones do not wrap, so they must be in
+/// order, and must overlap the bounds, although they may extend past them.
+fn check_bbox(bbox: [f64; 4], coordinate_system: CoordinateSystem) -> PyResult<()> {
+    let invalid = |expected: String| {
+        Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Invalid bbox: {:?}, expected {}",
+            bbox, expected
+        )))
+    };
+    match coordinate_system {
+        CoordinateSystem::Geographic if bbox.iter().any(|c| c.is_nan()) => {
+            invalid("[min_lng, min_lat, max_lng, max_lat]".to_string())
+        }
+        CoordinateSystem::Cartesian { bounds }
+            if !(bbox[0] <= bbox[2] && bbox[1] <= bbox[3])
+                || bbox[0] > bounds[2]
+                || bbox[2] < bounds[0]
+                || bbox[1] > bounds[3]
+                || bbox[3] < bounds[1] =>
+        {
+            invalid(format!("[min_x, min_y, max_x, max_y] overlapping the bounds {:?}", bounds))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_tile(z: u8, x: u32, y: u32) -> PyResult<()> {
+    if z > 31 || u64::from(x) >= 1 << z || u64::from(y) >= 1 << z {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Invalid tile coordinates: {}/{}/{}",
+            z, x, y
+        )));
+    }
+    Ok(())
+}
+
+fn feature_to_pyobject(py: Python, feature: &Feature) -> PyResult<PyObject> {
+    let py_feature = PyDict::new(py);
+    // Cluster features get a stringified copy of `cluster_id` as their id;
+    // only pass through the ids points were loaded with
+    if !feature.contains_property("cluster") {
+        match &feature.id {
+            Some(Id::Number(n)) => py_feature.set_item("id", json_to_pyobject(py, &serde_json::Value::Number(n.clone())))?,
+            Some(Id::String(s)) => py_feature.set_item("id", s)?,
+            None => {}
+        }
+    }
+    if let Some(geometry) = &feature.geometry {
+        match &geometry.value {
+            geojson::Value::Point(coords) => {
+                let geometry_dict = PyDict::new(py);
+                geometry_dict.set_item("type", "Point")?;
+                geometry_dict.set_item("coordinates", coords)?;
+                py_feature.set_item("geometry", geometry_dict)?;
+            },
+            // Leaves loaded with `geometry_anchor`
+            _ => {
+                let geometry = serde_json::Value::Object(JsonObject::from(geometry));
+                py_feature.set_item("geometry", json_to_pyobject(py, &geometry))?;
+            }
+        }
+    }
+
+    if let Some(properties) = &feature.properties {
+        let properties_dict = PyDict::new(py);
+        for (key, value) in properties {
+            let py_value = json_to_pyobject(py, value);
+            properties_dict.set_item(key, py_value)?;
+        }
+        py_feature.set_item("properties", properties_dict)?;
+    } else {
+        py_feature.set_item("properties", PyDict::new(py))?;
+    }
+
+    py_feature.set_item("type", "Feature")?;
+    Ok(py_feature.to_object(py))
+}
+
+/// Tile features carry already rounded tile-local coordinates, so hand them
+/// to Python as ints rather than floats.
+fn tile_feature_to_pyobject(py: Python, feature: &Feature) -> PyResult<PyObject> {
+    let py_feature = feature_to_pyobject(py, feature)?;
+    if let Some(geometry) = &feature.geometry {
+        if let geojson::Value::Point(coords) = &geometry.value {
+            let tile_coords: Vec<i64> = coords.iter().map(|c| *c as i64).collect();
+            let geometry_dict = PyDict::new(py);
+            geometry_dict.set_item("type", "Point")?;
+            geometry_dict.set_item("coordinates", tile_coords)?;
+            py_feature.as_ref(py).set_item("geometry", geometry_dict)?;
+        }
+    }
+    Ok(py_feature)
+}
+
 fn json_to_pyobject(py: Python, value: &serde_json::Value) -> PyObject {
     match value {
         serde_json::Value::Null => py.None(),
@@ -138,6 +1419,8 @@ fn json_to_pyobject(py: Python, value: &serde_json::Value) -> PyObject {
         serde_json::Value::Number(n) => {
             if let Some(i) = n.as_i64() {
                 i.into_py(py)
+            } else if let Some(u) = n.as_u64() {
+                u.into_py(py)
             } else if let Some(f) = n.as_f64() {
                 f.into_py(py)
             } else {
@@ -163,3 +1446,699 @@ fn json_to_pyobject(py: Python, value: &serde_json::Value) -> PyObject {
         },
     }
 }
+
+/// Inverse of `json_to_pyobject`. On failure returns the (possibly nested)
+/// object that has no JSON representation.
+fn pyobject_to_json(obj: &PyAny) -> Result<serde_json::Value, &PyAny> {
+    if obj.is_none() {
+        Ok(serde_json::Value::Null)
+    } else if let Ok(b) = obj.downcast::<PyBool>() {
+        Ok(serde_json::Value::Bool(b.is_true()))
+    } else if obj.is_instance_of::<PyLong>() {
+        if let Ok(i) = obj.extract::<i64>() {
+            Ok(i.into())
+        } else if let Ok(u) = obj.extract::<u64>() {
+            Ok(u.into())
+        } else {
+            Err(obj)
+        }
+    } else if let Ok(f) = obj.downcast::<PyFloat>() {
+        // NaN and infinities have no JSON form; JSON.stringify maps them to null too
+        Ok(serde_json::Number::from_f64(f.value())
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null))
+    } else if let Ok(s) = obj.downcast::<PyString>() {
+        Ok(serde_json::Value::String(s.to_string_lossy().into_owned()))
+    } else if let Ok(dict) = obj.downcast::<PyDict>() {
+        let mut map = JsonObject::new();
+        for (k, v) in dict {
+            let key = k.downcast::<PyString>().map_err(|_| k)?;
+            map.insert(key.to_string_lossy().into_owned(), pyobject_to_json(v)?);
+        }
+        Ok(serde_json::Value::Object(map))
+    } else if let Ok(list) = obj.downcast::<PyList>() {
+        list.iter().map(pyobject_to_json).collect()
+    } else if let Ok(tuple) = obj.downcast::<PyTuple>() {
+        tuple.iter().map(pyobject_to_json).collect()
+    } else if let Some(index) = obj.call_method0("__index__").ok().filter(|index| index.is_instance_of::<PyLong>()) {
+        // Other integers, such as numpy's
+        pyobject_to_json(index).map_err(|_| obj)
+    } else {
+        Err(obj)
+    }
+}
+
+fn type_name(obj: &PyAny) -> String {
+    obj.get_type()
+        .name()
+        .map(|name| name.to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run Python `code` with the module imported as `ps`, failing the test
+    /// with the Python traceback if it raises.
+    fn run(code: &str) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "pysupercluster").unwrap();
+            pysupercluster(py, module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("ps", module).unwrap();
+            if let Err(err) = py.run(code, Some(globals), None) {
+                err.print(py);
+                panic!("{}", err);
+            }
+        })
+    }
+
+    #[test]
+    fn test_empty_and_unloaded_indexes() {
+        run(r#"
+world = [-180, -85, 180, 85]
+empty = ps.PySupercluster()
+empty.load([])
+for index in [ps.PySupercluster(), empty]:
+    assert index.get_clusters(world, 0) == []
+    assert index.get_clusters_json(world, 0) == '{"features":[],"type":"FeatureCollection"}'
+    assert index.get_tile(0, 0, 0) is None
+    assert index.get_tile_mvt(0, 0, 0) == b""
+    for query in [index.get_children, index.get_cluster_expansion_zoom, lambda id: index.get_leaves(id, 10, 0)]:
+        try:
+            query(0)
+            assert False
+        except ValueError:
+            pass
+"#);
+    }
+
+    #[test]
+    fn test_failed_load_keeps_index() {
+        run(r#"
+world = [-180, -85, 180, 85]
+points = [{"geometry": {"type": "Point", "coordinates": [0, 0]}} for _ in range(2)]
+def reduce(accumulated, properties):
+    raise RuntimeError("boom")
+index = ps.PySupercluster(map=lambda properties: {}, reduce=reduce)
+try:
+    index.load(points)
+    assert False
+except RuntimeError:
+    pass
+assert index.get_clusters(world, 0) == []
+assert index.get_tile(0, 0, 0) is None
+
+index = ps.PySupercluster(map=lambda properties: {}, reduce=reduce)
+index.load(points[:1])
+try:
+    index.load(points)
+    assert False
+except RuntimeError:
+    pass
+assert len(index.get_clusters(world, 0)) == 1
+"#);
+    }
+
+    #[test]
+    fn test_open_mmap() {
+        run(r#"
+import os, tempfile
+world = [-180, -85, 180, 85]
+points = [
+    {"geometry": {"type": "Point", "coordinates": [i % 10, i // 10]}, "properties": {"n": i}, "id": i}
+    for i in range(100)
+]
+index = ps.PySupercluster(aggregations={"n": "sum"})
+index.load(points)
+with tempfile.TemporaryDirectory() as directory:
+    path = os.path.join(directory, "index.bin")
+    index.save(path)
+    mapped = ps.PySupercluster.open_mmap(path)
+    for zoom in range(18):
+        assert mapped.get_clusters(world, zoom) == index.get_clusters(world, zoom)
+    assert mapped.get_tile(1, 1, 0) == index.get_tile(1, 1, 0)
+    assert sorted(leaf["id"] for leaf in mapped.get_leaves(mapped.get_clusters(world, 0)[0]["properties"]["cluster_id"], 1000, 0)) == list(range(100))
+
+    # A point record with an unknown geometry tag
+    data = bytearray(open(path, "rb").read())
+    data[data.index(b'\x01\x02\x00\x00\x00\x00\x00\x00\x00')] = 0xff
+    open(path, "wb").write(data)
+    try:
+        ps.PySupercluster.open_mmap(path)
+        assert False
+    except ValueError as err:
+        assert str(err) == "Corrupt or truncated index data"
+
+    # Concurrent saves to the same path leave one of the indexes, and no temporary files
+    import threading
+    other = ps.PySupercluster(aggregations={"n": "sum"})
+    other.load(points[:50])
+    errors = []
+    def save(saved):
+        try:
+            for _ in range(5):
+                saved.save(path)
+        except Exception as err:
+            errors.append(err)
+    threads = [threading.Thread(target=save, args=(saved,)) for saved in [index, other] * 2]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+    assert errors == [] and os.listdir(directory) == ["index.bin"], (errors, os.listdir(directory))
+    mapped = ps.PySupercluster.open_mmap(path)
+    assert mapped.get_clusters(world, 0) in [index.get_clusters(world, 0), other.get_clusters(world, 0)]
+"#);
+    }
+
+    #[test]
+    fn test_cartesian_bounds() {
+        run(r#"
+for bounds in [[10, 0, 0, 10], [0, 10, 10, 0], [0, 0, 0, 10], [0, 5, 10, 5], [0, 0, float("nan"), 10], [0, 0, float("inf"), 10]]:
+    try:
+        ps.PySupercluster(coordinate_system="cartesian", bounds=bounds)
+        assert False
+    except ValueError as err:
+        assert str(err).startswith("Invalid bounds"), err
+for make in [lambda: ps.PySupercluster(coordinate_system="cartesian"), lambda: ps.PySupercluster(bounds=[0, 0, 10, 10])]:
+    try:
+        make()
+        assert False
+    except ValueError:
+        pass
+
+index = ps.PySupercluster(coordinate_system="cartesian", bounds=[0, 0, 1000, 1000])
+index.load([{"geometry": {"type": "Point", "coordinates": [x, 500]}} for x in [100, 150, 900]])
+clusters = index.get_clusters([0, 0, 1000, 1000], 0)
+assert sorted(cluster["geometry"]["coordinates"][0] for cluster in clusters) == [125, 900]
+assert index.get_clusters([-100, 400, 0, 600], 0) == []
+
+# Geographic bounding boxes only have to be numbers
+geographic = ps.PySupercluster()
+geographic.load([{"geometry": {"type": "Point", "coordinates": [0, 0]}}])
+assert len(geographic.get_clusters([170, -100, -170, 100], 0)) == 0
+try:
+    geographic.get_clusters([0, 0, float("nan"), 10], 0)
+    assert False
+except ValueError as err:
+    assert str(err) == "Invalid bbox: [0.0, 0.0, NaN, 10.0], expected [min_lng, min_lat, max_lng, max_lat]", err
+
+# Cartesian ones do not wrap, and must overlap the bounds
+for bbox in [[900, 0, 100, 1000], [0, 1000, 1000, 0], [0, 0, float("nan"), 1000], [1001, 0, 2000, 1000], [0, -20, 1000, -10]]:
+    try:
+        index.get_clusters(bbox, 0)
+        assert False, bbox
+    except ValueError as err:
+        expected = "expected [min_x, min_y, max_x, max_y] overlapping the bounds [0.0, 0.0, 1000.0, 1000.0]"
+        assert str(err).endswith(expected), err
+"#);
+    }
+
+    #[test]
+    fn test_integer_like_ids() {
+        run(r#"
+class Int64:
+    """Stands in for numpy.int64, which is not an int but has __index__."""
+    def __init__(self, value):
+        self.value = value
+    def __index__(self):
+        return self.value
+
+world = [-180, -85, 180, 85]
+points = [{"geometry": {"type": "Point", "coordinates": [i, 0]}, "id": Int64(i), "properties": {"n": Int64(i)}} for i in range(3)]
+index = ps.PySupercluster(max_zoom=0)
+index.load(points)
+leaves = index.get_leaves(index.get_clusters(world, 0)[0]["properties"]["cluster_id"], 10, 0)
+assert sorted((leaf["id"], leaf["properties"]["n"]) for leaf in leaves) == [(0, 0), (1, 1), (2, 2)]
+
+index.remove([Int64(1)])
+index.update(Int64(2), {"geometry": {"type": "Point", "coordinates": [100, 0]}, "id": 2})
+assert sorted(feature["geometry"]["coordinates"][0] for feature in index.get_clusters(world, 1)) == [0, 100]
+try:
+    index.remove([object()])
+    assert False
+except TypeError:
+    pass
+"#);
+    }
+
+    #[test]
+    fn test_get_leaves_pagination() {
+        run(r#"
+world = [-180, -85, 180, 85]
+index = ps.PySupercluster()
+index.load([{"geometry": {"type": "Point", "coordinates": [i % 10, i // 10]}, "id": i} for i in range(100)])
+cluster_id = index.get_clusters(world, 0)[0]["properties"]["cluster_id"]
+
+leaves = index.get_leaves(cluster_id, 1000, 0)
+assert sorted(leaf["id"] for leaf in leaves) == list(range(100))
+assert index.get_leaves(cluster_id) == leaves[:10]
+pages = [index.get_leaves(cluster_id, 7, offset) for offset in range(0, 100, 7)]
+assert [len(page) for page in pages] == [7] * 14 + [2]
+assert [leaf for page in pages for leaf in page] == leaves
+assert index.get_leaves(cluster_id, 10, 95) == leaves[95:]
+assert index.get_leaves(cluster_id, 10, 100) == []
+assert index.get_leaves(cluster_id, 10, 1000) == []
+assert index.get_leaves(cluster_id, 0, 0) == []
+assert index.get_leaves(cluster_id, 0, 50) == []
+"#);
+    }
+
+    #[test]
+    fn test_invalid_cluster_ids() {
+        run(r#"
+world = [-180, -85, 180, 85]
+def points(n, step):
+    return [{"geometry": {"type": "Point", "coordinates": [i * step, 0]}} for i in range(n)]
+def raises(query, cluster_id):
+    try:
+        query(cluster_id)
+        assert False, cluster_id
+    except ValueError as err:
+        assert str(err) == f"No cluster with the specified id: {cluster_id}"
+
+index = ps.PySupercluster()
+index.load(points(50, 0.5))
+other = ps.PySupercluster()
+other.load(points(20, 2))
+queries = [
+    other.get_children,
+    other.get_cluster_expansion_zoom,
+    other.decode_cluster_id,
+    lambda cluster_id: other.get_leaves(cluster_id, 10, 0),
+]
+
+def cluster_ids(index):
+    clusters = [cluster for zoom in range(17) for cluster in index.get_clusters(world, zoom)]
+    return {cluster["properties"]["cluster_id"] for cluster in clusters if "cluster_id" in cluster["properties"]}
+other_ids = cluster_ids(other)
+assert other_ids and cluster_ids(index) - other_ids
+
+# Cluster ids of another index, and point indexes
+for cluster_id in sorted(cluster_ids(index) - other_ids) + list(range(20)):
+    for query in queries:
+        raises(query, cluster_id)
+
+# The ids of the index itself are valid
+for cluster_id in other_ids:
+    for query in queries:
+        query(cluster_id)
+"#);
+    }
+
+    #[test]
+    fn test_max_zoom() {
+        run(r#"
+for max_zoom in [31, 255]:
+    try:
+        ps.PySupercluster(max_zoom=max_zoom)
+        assert False
+    except ValueError as err:
+        assert str(err) == f"max_zoom must be at most 30, got {max_zoom}"
+
+world = [-180, -85, 180, 85]
+index = ps.PySupercluster(max_zoom=30)
+index.load([{"geometry": {"type": "Point", "coordinates": [0, i * 1e-9]}} for i in range(2)])
+cluster_id = index.get_clusters(world, 30)[0]["properties"]["cluster_id"]
+assert index.get_cluster_expansion_zoom(cluster_id) == 31
+loaded = ps.PySupercluster.from_bytes(index.to_bytes())
+assert loaded.get_clusters(world, 30) == index.get_clusters(world, 30)
+"#);
+    }
+
+    #[test]
+    fn test_edits_match_load() {
+        run(r#"
+import random
+random.seed(1)
+world = [-180, -85, 180, 85]
+def point(id):
+    coordinates = [random.uniform(0, 20), random.uniform(0, 20)]
+    return {"id": id, "geometry": {"type": "Point", "coordinates": coordinates}, "properties": {"value": id}}
+mapped = []
+def map(properties):
+    mapped.append(properties["value"])
+    return {"sum": properties["value"]}
+def reduce(accumulated, properties):
+    accumulated["sum"] += properties["sum"]
+def snapshot(index):
+    clusters = [index.get_clusters(world, zoom) for zoom in range(18)]
+    children = [index.get_children(c["properties"]["cluster_id"]) for cs in clusters for c in cs if "cluster_id" in c["properties"]]
+    return clusters, children
+
+points = [point(id) for id in range(1000)]
+index = ps.PySupercluster(map=map, reduce=reduce)
+index.load(points)
+for round in range(2):
+    removed = random.sample([p["id"] for p in points], 5)
+    index.remove(removed)
+    points = [p for p in points if p["id"] not in removed]
+    moved = point(points[100]["id"])
+    index.update(moved["id"], moved)
+    points[100] = moved
+    added = [point(1000 + 10 * round + i) for i in range(3)]
+    index.add(added)
+    points += added
+
+    del mapped[:]
+    edited = snapshot(index)
+    assert len(mapped) < len(points) / 2
+    loaded = ps.PySupercluster(map=map, reduce=reduce)
+    loaded.load(points)
+    assert edited == snapshot(loaded)
+"#);
+    }
+
+    #[test]
+    fn test_filtered_queries() {
+        run(r#"
+import random
+random.seed(2)
+world = [-180, -85, 180, 85]
+def point(i):
+    coordinates = [random.uniform(0, 20), random.uniform(0, 20)]
+    return {"geometry": {"type": "Point", "coordinates": coordinates}, "properties": {"value": i, "open": i % 3 == 0}}
+index = ps.PySupercluster(aggregations={"value": "sum"}, filterable=["open"])
+index.load([point(i) for i in range(500)])
+is_open = ["==", "open", True]
+expected = [i for i in range(500) if i % 3 == 0]
+def cluster_ids(features):
+    return {f["properties"]["cluster_id"] for f in features if "cluster_id" in f["properties"]}
+
+# Clusters stand for their matching points only, under the ids of the index
+for zoom in range(18):
+    values = []
+    features = index.get_clusters(world, zoom, filter=is_open)
+    assert cluster_ids(features) <= cluster_ids(index.get_clusters(world, zoom))
+    for feature in features:
+        properties = feature["properties"]
+        if "cluster_id" not in properties:
+            assert properties["open"]
+            values.append(properties["value"])
+            continue
+        leaves = [leaf["properties"] for leaf in index.get_leaves(properties["cluster_id"], 1000, 0, filter=is_open)]
+        assert all(leaf["open"] for leaf in leaves)
+        assert properties["point_count"] == len(leaves) > 1
+        assert properties["value"] == sum(leaf["value"] for leaf in leaves)
+        children = index.get_children(properties["cluster_id"], filter=is_open)
+        assert sum(child["properties"].get("point_count", 1) for child in children) == len(leaves)
+        values += [leaf["value"] for leaf in leaves]
+    assert sorted(values) == expected, zoom
+
+    # Filters matching every point give the clusters of the index
+    assert index.get_clusters(world, zoom, filter=["has", "open"]) == index.get_clusters(world, zoom)
+
+tile = index.get_tile(0, 0, 0, filter=is_open)["features"]
+assert sum(f["properties"].get("point_count", 1) for f in tile) == len(expected)
+closed = index.get_clusters(world, 0, filter=["!", is_open])
+assert sum(f["properties"].get("point_count", 1) for f in closed) == 500 - len(expected)
+"#);
+    }
+
+    #[test]
+    fn test_geometry_anchor() {
+        run(r#"
+import math
+world = [-180, -85, 180, 85]
+square = {"type": "Polygon", "coordinates": [[[0, 0], [2, 0], [2, 2], [0, 2], [0, 0]]]}
+line = {"type": "LineString", "coordinates": [[4, 0], [4, 2]]}
+def feature(id, geometry):
+    return {"type": "Feature", "id": id, "geometry": geometry, "properties": {"name": id}}
+def point(id, coordinates):
+    return feature(id, {"type": "Point", "coordinates": coordinates})
+features = [feature("square", square), feature("line", line), point("point", [3, 1])]
+expected = {f["id"]: f["geometry"] for f in features}
+def geometries(features):
+    return {f["properties"]["name"]: f["geometry"] for f in features}
+
+index = ps.PySupercluster(geometry_anchor="centroid")
+index.load(features)
+
+# Leaves keep their original geometry
+assert geometries(index.get_clusters(world, 16)) == expected
+[cluster] = index.get_clusters(world, 0)
+cluster_id = cluster["properties"]["cluster_id"]
+assert geometries(index.get_leaves(cluster_id, 10, 0)) == expected
+leaves, clusters = [], [cluster_id]
+while clusters:
+    for child in index.get_children(clusters.pop()):
+        if "cluster_id" in child["properties"]:
+            clusters.append(child["properties"]["cluster_id"])
+        else:
+            leaves.append(child)
+assert geometries(leaves) == expected
+
+# Tiles have them at their anchor, like points loaded there
+anchored = ps.PySupercluster()
+anchored.load([point("square", [1, 1]), point("line", [4, 1]), features[2]])
+def tile(z, lng, lat):
+    y = (1 - math.log(math.tan(math.pi / 4 + math.radians(lat) / 2)) / math.pi) / 2
+    return z, int((lng + 180) / 360 * 2 ** z), int(y * 2 ** z)
+for z, x, y in [tile(0, 1, 1), tile(5, 1, 1), tile(16, 1, 1), tile(16, 4, 1)]:
+    assert index.get_tile(z, x, y) == anchored.get_tile(z, x, y) is not None, (z, x, y)
+    assert index.get_tile_mvt(z, x, y) == anchored.get_tile_mvt(z, x, y) != b"", (z, x, y)
+
+# The anchor is restored along with the index
+restored = ps.PySupercluster.from_bytes(index.to_bytes())
+for zoom in range(18):
+    assert restored.get_clusters(world, zoom) == index.get_clusters(world, zoom)
+restored.load(features[:2])
+assert geometries(restored.get_clusters(world, 16)) == {"square": square, "line": line}
+"#);
+    }
+
+    #[test]
+    fn test_load_geojson() {
+        run(r#"
+import json
+import os
+import tempfile
+world = [-180, -85, 180, 85]
+def point(id, coordinates):
+    return {"type": "Feature", "id": id, "geometry": {"type": "Point", "coordinates": coordinates}, "properties": {"name": id}}
+features = [point(1, [0, 0]), point(2, [1, 1]), point(3, [40, 10])]
+collection = {"type": "FeatureCollection", "features": features}
+expected = ps.PySupercluster()
+expected.load(features)
+def clusters(index):
+    return [index.get_clusters(world, zoom) for zoom in range(18)]
+
+# A FeatureCollection or a bare array of features, from a string or a file
+for geojson in [collection, features]:
+    index = ps.PySupercluster()
+    assert index.load_geojson_str(json.dumps(geojson)) is None
+    assert clusters(index) == clusters(expected)
+
+    with tempfile.TemporaryDirectory() as directory:
+        path = os.path.join(directory, "points.geojson")
+        with open(path, "w") as file:
+            json.dump(geojson, file)
+        index = ps.PySupercluster()
+        assert index.load_geojson_file(path) is None
+        assert clusters(index) == clusters(expected)
+
+# Invalid JSON and missing files leave the index as it was
+for load, arg, error in [
+    (index.load_geojson_str, '{"type": "FeatureCollection", "features": [', ValueError),
+    (index.load_geojson_str, '{"type": "Point", "coordinates": [0, 0]}', ValueError),
+    (index.load_geojson_file, os.path.join(tempfile.gettempdir(), "missing", "points.geojson"), OSError),
+]:
+    try:
+        load(arg)
+        assert False, arg
+    except error as err:
+        assert error is OSError or str(err).startswith(("Invalid GeoJSON", "Expected")), err
+    assert clusters(index) == clusters(expected)
+
+# Bad features are skipped or collected on request
+bad = {"type": "Feature", "geometry": {"type": "Point", "coordinates": [0]}, "properties": {}}
+geojson = json.dumps({"type": "FeatureCollection", "features": features[:1] + [bad] + features[1:]})
+try:
+    index.load_geojson_str(geojson)
+    assert False
+except ValueError as err:
+    assert str(err).startswith("Feature 1 "), err
+assert index.load_geojson_str(geojson, on_error="skip") is None
+assert clusters(index) == clusters(expected)
+[(position, err)] = index.load_geojson_str(geojson, on_error="collect")
+assert position == 1 and isinstance(err, ValueError), err
+assert clusters(index) == clusters(expected)
+"#);
+    }
+
+    #[test]
+    fn test_property_conversion() {
+        run(r#"
+import json
+world = [-180, -85, 180, 85]
+def point(properties, coordinates=[0, 0]):
+    return {"geometry": {"type": "Point", "coordinates": coordinates}, "properties": properties}
+properties = {
+    "quotes": "it's a \"quoted\" name",
+    "missing": None,
+    "pair": (1, "two", None),
+    "nested": {"list": [1.5, {"deep": (True, False)}], "empty": {}},
+    "big": 2 ** 63,
+}
+converted = dict(properties, pair=[1, "two", None], nested={"list": [1.5, {"deep": [True, False]}], "empty": {}})
+
+index = ps.PySupercluster()
+index.load([point(properties)])
+[feature] = index.get_clusters(world, 0)
+assert feature["properties"] == converted, feature
+[feature] = json.loads(index.get_clusters_json(world, 0))["features"]
+assert feature["properties"] == converted, feature
+
+# Values without a JSON form name the feature and the property
+for value, type_name in [({1, 2}, "set"), ({"deep": [object()]}, "object"), ((1, b"raw"), "bytes")]:
+    try:
+        index.load([point({}), point({}), point({"ok": 1, "bad": value})])
+        assert False, value
+    except TypeError as err:
+        assert str(err) == f"Unsupported type '{type_name}' in property 'bad' of feature 2", err
+"#);
+    }
+
+    #[test]
+    fn test_python_reducer() {
+        run(r#"
+import random
+random.seed(3)
+world = [-180, -85, 180, 85]
+def point(i):
+    coordinates = [random.uniform(0, 20), random.uniform(0, 20)]
+    properties = {"value": i, "kind": "abc"[i % 3]}
+    return {"geometry": {"type": "Point", "coordinates": coordinates}, "properties": properties}
+def map(properties):
+    return {"sum": properties["value"], "kinds": [properties["kind"]]}
+def reduce(accumulated, properties):
+    accumulated["sum"] += properties["sum"]
+    accumulated["kinds"] = sorted(set(accumulated["kinds"]) | set(properties["kinds"]))
+def reduced(feature):
+    properties = feature["properties"]
+    if "cluster_id" in properties:
+        return properties["sum"], properties["kinds"]
+    return properties["value"], [properties["kind"]]
+def combined(features):
+    values = [reduced(feature) for feature in features]
+    return sum(value for value, _ in values), sorted({kind for _, kinds in values for kind in kinds})
+
+points = [point(i) for i in range(300)]
+index = ps.PySupercluster(map=map, reduce=reduce)
+index.load(points)
+clustered = 0
+for zoom in range(17):
+    for feature in index.get_clusters(world, zoom):
+        if "cluster_id" not in feature["properties"]:
+            assert set(feature["properties"]) == {"value", "kind"}
+            continue
+        clustered += 1
+        cluster_id = feature["properties"]["cluster_id"]
+        leaves = index.get_leaves(cluster_id, len(points), 0)
+        assert reduced(feature) == combined(leaves)
+        # Children carry their own reduced values, which add up to the cluster's
+        assert reduced(feature) == combined(index.get_children(cluster_id))
+assert clustered > 20
+
+# Without map, clusters start from the properties of their first point
+def reduce_values(accumulated, properties):
+    accumulated["value"] += properties["value"]
+index = ps.PySupercluster(reduce=reduce_values)
+index.load(points)
+for feature in index.get_clusters(world, 0):
+    leaves = index.get_leaves(feature["properties"]["cluster_id"], len(points), 0)
+    assert feature["properties"]["value"] == sum(leaf["properties"]["value"] for leaf in leaves)
+    assert feature["properties"]["kind"] in "abc"
+"#);
+    }
+
+    #[test]
+    fn test_get_clusters_json() {
+        run(r#"
+import json
+import random
+random.seed(4)
+world = [-180, -85, 180, 85]
+def point(i):
+    coordinates = [random.uniform(-10, 10), random.uniform(-10, 10)]
+    feature = {"geometry": {"type": "Point", "coordinates": coordinates}}
+    if i % 4:
+        feature["id"] = i if i % 2 else f"point-{i}"
+        feature["properties"] = {"value": i / 3, "open": i % 3 == 0, "name": f"it's \"{i}\""}
+    return feature
+index = ps.PySupercluster(aggregations={"value": "sum"}, weight_property="value", filterable=["open"])
+index.load([point(i) for i in range(400)])
+
+for zoom in range(18):
+    for bbox, filter in [(world, None), ([-5, -5, 5, 5], None), (world, ["==", "open", True])]:
+        expected = {"type": "FeatureCollection", "features": index.get_clusters(bbox, zoom, filter=filter)}
+        assert json.loads(index.get_clusters_json(bbox, zoom, filter=filter)) == expected, zoom
+        assert json.loads(index.get_clusters_json(bbox, zoom, as_bytes=True, filter=filter)) == expected, zoom
+"#);
+    }
+
+    #[test]
+    fn test_get_clusters_arrays() {
+        run(r#"
+import random
+random.seed(5)
+world = [-180, -85, 180, 85]
+def point(i):
+    coordinates = [random.uniform(-10, 10), random.uniform(-10, 10)]
+    return {"geometry": {"type": "Point", "coordinates": coordinates}, "properties": {"index": i, "open": i % 2 == 0}}
+index = ps.PySupercluster(filterable=["open"])
+index.load([point(i) for i in range(300)])
+
+# The arrays are numpy arrays, which are only checked where numpy is installed
+try:
+    import numpy
+except ImportError:
+    numpy = None
+if numpy is not None:
+    dtypes = {"lon": "float64", "lat": "float64", "is_cluster": "bool", "cluster_id": "int64", "point_count": "int64", "point_index": "int64"}
+    for zoom in range(18):
+        for bbox, filter in [(world, None), ([-5, -5, 5, 5], None), (world, ["==", "open", True])]:
+            features = index.get_clusters(bbox, zoom, filter=filter)
+            arrays = index.get_clusters_arrays(bbox, zoom, filter=filter)
+            assert {key: str(array.dtype) for key, array in arrays.items()} == dtypes
+            assert all(len(array) == len(features) for array in arrays.values())
+            for i, feature in enumerate(features):
+                properties = feature["properties"]
+                assert [arrays["lon"][i], arrays["lat"][i]] == feature["geometry"]["coordinates"]
+                assert arrays["is_cluster"][i] == ("cluster_id" in properties)
+                assert arrays["cluster_id"][i] == properties.get("cluster_id", -1)
+                assert arrays["point_count"][i] == properties.get("point_count", 1)
+                assert arrays["point_index"][i] == properties.get("index", -1)
+"#);
+    }
+
+    #[test]
+    fn test_invalid_radii() {
+        run(r#"
+def raises(make):
+    try:
+        make()
+        assert False
+    except ValueError:
+        pass
+
+for radius in [0, -1, float("nan"), float("inf")]:
+    raises(lambda: ps.PySupercluster(radius=radius))
+for schedule in [{3: 0}, {3: -10}, [40, float("nan")]]:
+    raises(lambda: ps.PySupercluster(radius_schedule=schedule))
+
+def point(radius):
+    return {"geometry": {"type": "Point", "coordinates": [0, 0]}, "properties": {"radius": radius}}
+
+index = ps.PySupercluster(radius_property="radius")
+for radius in [-5, 0, "x", float("nan")]:
+    raises(lambda: index.load([point(radius)]))
+index.load([point(None), point(10)])
+assert len(index.get_clusters([-180, -85, 180, 85], 0)) == 1
+"#);
+    }
+}
+
@@ -0,0 +1,471 @@
+//! Minimal Mapbox Vector Tile (v2.1) encoder for the point layers produced by
+//! `Selected::get_tile`. Only the subset of the protobuf schema needed for
+//! a single layer of point features is written.
+
+use std::collections::HashMap;
+
+use geojson::feature::Id;
+use geojson::Feature;
+use geojson::Value::Point;
+
+const WIRE_VARINT: u32 = 0;
+const WIRE_FIXED64: u32 = 1;
+const WIRE_LEN: u32 = 2;
+
+const TILE_LAYERS: u32 = 3;
+
+const LAYER_NAME: u32 = 1;
+const LAYER_FEATURES: u32 = 2;
+const LAYER_KEYS: u32 = 3;
+const LAYER_VALUES: u32 = 4;
+const LAYER_EXTENT: u32 = 5;
+const LAYER_VERSION: u32 = 15;
+
+const FEATURE_ID: u32 = 1;
+const FEATURE_TAGS: u32 = 2;
+const FEATURE_TYPE: u32 = 3;
+const FEATURE_GEOMETRY: u32 = 4;
+
+const VALUE_STRING: u32 = 1;
+const VALUE_DOUBLE: u32 = 3;
+const VALUE_UINT: u32 = 5;
+const VALUE_SINT: u32 = 6;
+const VALUE_BOOL: u32 = 7;
+
+const GEOM_POINT: u64 = 1;
+const CMD_MOVE_TO: u32 = 1;
+
+/// Encode tile features into a tile holding one layer named `name`.
+///
+/// Features are expected in tile-local coordinates, as returned by
+/// `Selected::get_tile`. An empty feature list yields an empty tile.
+/// Coordinates have to fit the 32-bit signed integers of MVT geometries.
+pub fn encode_tile(name: &str, extent: u32, features: &[Feature]) -> Result<Vec<u8>, String> {
+    if features.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut layer = LayerBuilder::default();
+    for feature in features {
+        layer.add_feature(feature)?;
+    }
+
+    let mut tile = Vec::new();
+    write_bytes(&mut tile, TILE_LAYERS, &layer.finish(name, extent));
+    Ok(tile)
+}
+
+/// Accumulates features of a layer together with its deduplicated key and
+/// value tables.
+#[derive(Default)]
+struct LayerBuilder {
+    features: Vec<Vec<u8>>,
+    keys: Vec<String>,
+    key_index: HashMap<String, u32>,
+    values: Vec<Vec<u8>>,
+    value_index: HashMap<Vec<u8>, u32>,
+}
+
+impl LayerBuilder {
+    fn add_feature(&mut self, feature: &Feature) -> Result<(), String> {
+        let coords = match feature.geometry.as_ref().map(|g| &g.value) {
+            Some(Point(coords)) => coords,
+            _ => return Ok(()),
+        };
+        let (x, y) = match (to_i32(coords[0]), to_i32(coords[1])) {
+            (Some(x), Some(y)) => (x, y),
+            _ => {
+                return Err(format!(
+                    "Tile coordinates [{}, {}] do not fit MVT geometries",
+                    coords[0], coords[1]
+                ))
+            }
+        };
+
+        let mut tags = Vec::new();
+        if let Some(properties) = &feature.properties {
+            for (key, value) in properties {
+                if let Some(value) = encode_value(value) {
+                    tags.push(self.key(key));
+                    tags.push(self.value(value));
+                }
+            }
+        }
+
+        let mut encoded = Vec::new();
+        if let Some(id) = feature_id(feature) {
+            write_varint_field(&mut encoded, FEATURE_ID, id);
+        }
+        if !tags.is_empty() {
+            write_packed(&mut encoded, FEATURE_TAGS, &tags);
+        }
+        write_varint_field(&mut encoded, FEATURE_TYPE, GEOM_POINT);
+        write_packed(
+            &mut encoded,
+            FEATURE_GEOMETRY,
+            &[
+                command(CMD_MOVE_TO, 1),
+                zigzag(x.into()) as u32,
+                zigzag(y.into()) as u32,
+            ],
+        );
+        self.features.push(encoded);
+        Ok(())
+    }
+
+    fn key(&mut self, key: &str) -> u32 {
+        if let Some(&index) = self.key_index.get(key) {
+            return index;
+        }
+        let index = self.keys.len() as u32;
+        self.keys.push(key.to_string());
+        self.key_index.insert(key.to_string(), index);
+        index
+    }
+
+    fn value(&mut self, value: Vec<u8>) -> u32 {
+        if let Some(&index) = self.value_index.get(&value) {
+            return index;
+        }
+        let index = self.values.len() as u32;
+        self.values.push(value.clone());
+        self.value_index.insert(value, index);
+        index
+    }
+
+    fn finish(self, name: &str, extent: u32) -> Vec<u8> {
+        let mut layer = Vec::new();
+        write_varint_field(&mut layer, LAYER_VERSION, 2);
+        write_bytes(&mut layer, LAYER_NAME, name.as_bytes());
+        for feature in &self.features {
+            write_bytes(&mut layer, LAYER_FEATURES, feature);
+        }
+        for key in &self.keys {
+            write_bytes(&mut layer, LAYER_KEYS, key.as_bytes());
+        }
+        for value in &self.values {
+            write_bytes(&mut layer, LAYER_VALUES, value);
+        }
+        write_varint_field(&mut layer, LAYER_EXTENT, u64::from(extent));
+        layer
+    }
+}
+
+/// Cluster features carry their cluster id as a numeric string, points keep
+/// whatever id they were loaded with; only non-negative integers fit MVT ids.
+fn feature_id(feature: &Feature) -> Option<u64> {
+    match feature.id.as_ref()? {
+        Id::String(s) => s.parse().ok(),
+        Id::Number(n) => n.as_u64(),
+    }
+}
+
+/// Encode a property as an MVT `Value` message. Nested arrays and objects have
+/// no MVT representation and are stored as JSON strings; nulls are dropped.
+fn encode_value(value: &serde_json::Value) -> Option<Vec<u8>> {
+    let mut encoded = Vec::new();
+    match value {
+        serde_json::Value::Null => return None,
+        serde_json::Value::Bool(b) => write_varint_field(&mut encoded, VALUE_BOOL, u64::from(*b)),
+        serde_json::Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                write_varint_field(&mut encoded, VALUE_UINT, u);
+            } else if let Some(i) = n.as_i64() {
+                write_varint_field(&mut encoded, VALUE_SINT, zigzag(i));
+            } else {
+                let f = n.as_f64().unwrap_or(f64::NAN);
+                write_tag(&mut encoded, VALUE_DOUBLE, WIRE_FIXED64);
+                encoded.extend_from_slice(&f.to_le_bytes());
+            }
+        }
+        serde_json::Value::String(s) => write_bytes(&mut encoded, VALUE_STRING, s.as_bytes()),
+        serde_json::Value::Array(_) | serde_json::Value::Object(_) => {
+            write_bytes(&mut encoded, VALUE_STRING, value.to_string().as_bytes())
+        }
+    }
+    Some(encoded)
+}
+
+/// A tile coordinate as an MVT geometry integer, if it is one.
+fn to_i32(c: f64) -> Option<i32> {
+    (c >= i32::MIN as f64 && c <= i32::MAX as f64).then_some(c as i32)
+}
+
+fn command(id: u32, count: u32) -> u32 {
+    (id & 0x7) | (count << 3)
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_tag(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    write_varint(buf, u64::from((field << 3) | wire_type));
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    write_tag(buf, field, WIRE_VARINT);
+    write_varint(buf, value);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_tag(buf, field, WIRE_LEN);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_packed(buf: &mut Vec<u8>, field: u32, values: &[u32]) {
+    let mut packed = Vec::new();
+    for &value in values {
+        write_varint(&mut packed, u64::from(value));
+    }
+    write_bytes(buf, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geojson::{Geometry, JsonObject};
+    use serde_json::json;
+
+    /// A decoded protobuf field.
+    #[derive(Debug, PartialEq)]
+    enum Field {
+        Varint(u64),
+        Fixed64([u8; 8]),
+        Bytes(Vec<u8>),
+    }
+
+    fn read_varint(bytes: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[*pos];
+            *pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    /// The fields of a message, in order.
+    fn decode(bytes: &[u8]) -> Vec<(u32, Field)> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let tag = read_varint(bytes, &mut pos);
+            let field = match (tag & 0x7) as u32 {
+                WIRE_VARINT => Field::Varint(read_varint(bytes, &mut pos)),
+                WIRE_FIXED64 => {
+                    pos += 8;
+                    Field::Fixed64(bytes[pos - 8..pos].try_into().unwrap())
+                }
+                WIRE_LEN => {
+                    let len = read_varint(bytes, &mut pos) as usize;
+                    pos += len;
+                    Field::Bytes(bytes[pos - len..pos].to_vec())
+                }
+                wire_type => panic!("unexpected wire type {}", wire_type),
+            };
+            fields.push(((tag >> 3) as u32, field));
+        }
+        fields
+    }
+
+    fn bytes_of(fields: &[(u32, Field)], number: u32) -> Vec<&[u8]> {
+        fields
+            .iter()
+            .filter_map(|(n, field)| match field {
+                Field::Bytes(bytes) if *n == number => Some(bytes.as_slice()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn varint_of(fields: &[(u32, Field)], number: u32) -> Option<u64> {
+        fields.iter().find_map(|(n, field)| match field {
+            Field::Varint(value) if *n == number => Some(*value),
+            _ => None,
+        })
+    }
+
+    fn packed(bytes: &[u8]) -> Vec<u64> {
+        let mut values = Vec::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            values.push(read_varint(bytes, &mut pos));
+        }
+        values
+    }
+
+    fn feature(id: Option<Id>, x: f64, y: f64, properties: serde_json::Value) -> Feature {
+        Feature {
+            id,
+            geometry: Some(Geometry::new(Point(vec![x, y]))),
+            properties: match properties {
+                serde_json::Value::Object(properties) => Some(properties),
+                _ => Some(JsonObject::new()),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// The single layer of a tile.
+    fn layer(tile: &[u8]) -> Vec<(u32, Field)> {
+        let tile = decode(tile);
+        let layers = bytes_of(&tile, TILE_LAYERS);
+        assert_eq!((tile.len(), layers.len()), (1, 1));
+        decode(layers[0])
+    }
+
+    #[test]
+    fn test_layer_header() {
+        let tile = encode_tile("clusters", 4096, &[feature(None, 1.0, 2.0, json!({}))]).unwrap();
+        let layer = layer(&tile);
+
+        assert_eq!(varint_of(&layer, LAYER_VERSION), Some(2));
+        assert_eq!(bytes_of(&layer, LAYER_NAME), vec![b"clusters"]);
+        assert_eq!(varint_of(&layer, LAYER_EXTENT), Some(4096));
+        assert_eq!(bytes_of(&layer, LAYER_FEATURES).len(), 1);
+    }
+
+    #[test]
+    fn test_empty_tile() {
+        assert_eq!(encode_tile("clusters", 4096, &[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_geometry() {
+        let tile = encode_tile("layer", 512, &[feature(None, 25.0, -3.0, json!({}))]).unwrap();
+        let layer = layer(&tile);
+        let feature = decode(bytes_of(&layer, LAYER_FEATURES)[0]);
+
+        assert_eq!(varint_of(&feature, FEATURE_TYPE), Some(GEOM_POINT));
+        assert_eq!(
+            packed(bytes_of(&feature, FEATURE_GEOMETRY)[0]),
+            vec![9, 50, 5]
+        );
+    }
+
+    #[test]
+    fn test_coordinates_out_of_range() {
+        for x in [2_147_483_648.0, -2_147_483_649.0, f64::NAN] {
+            assert!(encode_tile("layer", 512, &[feature(None, x, 0.0, json!({}))]).is_err());
+        }
+        let tile = encode_tile(
+            "layer",
+            512,
+            &[feature(None, -2_147_483_648.0, 2_147_483_647.0, json!({}))],
+        )
+        .unwrap();
+        let feature = decode(bytes_of(&layer(&tile), LAYER_FEATURES)[0]);
+        assert_eq!(
+            packed(bytes_of(&feature, FEATURE_GEOMETRY)[0]),
+            vec![9, u64::from(u32::MAX), u64::from(u32::MAX - 1)]
+        );
+    }
+
+    #[test]
+    fn test_ids() {
+        let features = [
+            feature(Some(Id::Number(7.into())), 0.0, 0.0, json!({})),
+            feature(Some(Id::String("123".to_string())), 0.0, 0.0, json!({})),
+            feature(Some(Id::String("abc".to_string())), 0.0, 0.0, json!({})),
+            feature(Some(Id::Number((-1).into())), 0.0, 0.0, json!({})),
+        ];
+        let tile = encode_tile("layer", 512, &features).unwrap();
+        let layer = layer(&tile);
+        let ids: Vec<_> = bytes_of(&layer, LAYER_FEATURES)
+            .into_iter()
+            .map(|feature| varint_of(&decode(feature), FEATURE_ID))
+            .collect();
+
+        assert_eq!(ids, vec![Some(7), Some(123), None, None]);
+    }
+
+    #[test]
+    fn test_tags_and_values() {
+        let features = [
+            feature(
+                None,
+                0.0,
+                0.0,
+                json!({"count": 3, "name": "a", "cluster": true}),
+            ),
+            feature(
+                None,
+                0.0,
+                0.0,
+                json!({"count": 3, "name": "b", "delta": -5, "mean": 1.5}),
+            ),
+            feature(None, 0.0, 0.0, json!({"missing": null, "list": [1, 2]})),
+        ];
+        let tile = encode_tile("layer", 512, &features).unwrap();
+        let layer = layer(&tile);
+
+        let keys: Vec<_> = bytes_of(&layer, LAYER_KEYS)
+            .into_iter()
+            .map(|key| std::str::from_utf8(key).unwrap())
+            .collect();
+        let values: Vec<_> = bytes_of(&layer, LAYER_VALUES)
+            .into_iter()
+            .map(decode)
+            .collect();
+        // Keys and values are shared between features, nulls dropped
+        assert_eq!(
+            keys,
+            vec!["cluster", "count", "name", "delta", "mean", "list"]
+        );
+        assert_eq!(
+            values,
+            vec![
+                vec![(VALUE_BOOL, Field::Varint(1))],
+                vec![(VALUE_UINT, Field::Varint(3))],
+                vec![(VALUE_STRING, Field::Bytes(b"a".to_vec()))],
+                vec![(VALUE_SINT, Field::Varint(9))],
+                vec![(VALUE_DOUBLE, Field::Fixed64(1.5_f64.to_le_bytes()))],
+                vec![(VALUE_STRING, Field::Bytes(b"b".to_vec()))],
+                vec![(VALUE_STRING, Field::Bytes(b"[1,2]".to_vec()))],
+            ]
+        );
+
+        let tags: Vec<_> = bytes_of(&layer, LAYER_FEATURES)
+            .into_iter()
+            .map(|feature| {
+                bytes_of(&decode(feature), FEATURE_TAGS)
+                    .first()
+                    .map(|tags| packed(tags))
+            })
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                Some(vec![0, 0, 1, 1, 2, 2]),
+                Some(vec![1, 1, 3, 3, 4, 4, 2, 5]),
+                Some(vec![5, 6]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zigzag_and_command() {
+        assert_eq!(
+            [0, -1, 1, -2, 2, i64::from(i32::MAX), i64::from(i32::MIN)].map(zigzag),
+            [0, 1, 2, 3, 4, 4_294_967_294, 4_294_967_295]
+        );
+        assert_eq!(zigzag(i64::MIN), u64::MAX);
+        assert_eq!(command(CMD_MOVE_TO, 1), 9);
+        assert_eq!(command(2, 3), 26);
+        assert_eq!(command(7, 0), 7);
+    }
+}
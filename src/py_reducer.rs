@@ -0,0 +1,93 @@
+//! Python `map`/`reduce` callables plugged into the clustering engine, with
+//! the semantics of the JavaScript supercluster options of the same names.
+
+use geojson::JsonObject;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::supercluster::ReduceError;
+use crate::supercluster::Reducer;
+use crate::json_to_pyobject;
+use crate::pyobject_to_json;
+use crate::type_name;
+
+/// `map(properties) -> dict` is called with the properties of every point,
+/// `reduce(accumulated, properties)` updates the `accumulated` dict of a
+/// cluster in place. Both run with the GIL, which they take themselves so that
+/// loading can otherwise run without it.
+#[derive(Debug)]
+pub struct PyReducer {
+    map: Option<PyObject>,
+    reduce: PyObject,
+}
+
+impl PyReducer {
+    pub fn new(map: Option<PyObject>, reduce: PyObject) -> Self {
+        PyReducer { map, reduce }
+    }
+}
+
+impl Reducer for PyReducer {
+    fn map(&self, properties: &JsonObject) -> Result<JsonObject, ReduceError> {
+        let map = match &self.map {
+            Some(map) => map,
+            None => return Ok(properties.clone()),
+        };
+        Python::with_gil(|py| {
+            let mapped = map.call1(py, (json_object_to_pydict(py, properties)?,))?;
+            pydict_to_json_object(mapped.as_ref(py), "map")
+        })
+        .map_err(|err| Box::new(err) as ReduceError)
+    }
+
+    fn reduce(&self, accumulated: &mut JsonObject, properties: &JsonObject) -> Result<(), ReduceError> {
+        Python::with_gil(|py| {
+            let py_accumulated = json_object_to_pydict(py, accumulated)?;
+            self.reduce
+                .call1(py, (py_accumulated, json_object_to_pydict(py, properties)?))?;
+            *accumulated = pydict_to_json_object(py_accumulated, "reduce")?;
+            Ok(())
+        })
+        .map_err(|err: PyErr| Box::new(err) as ReduceError)
+    }
+}
+
+fn json_object_to_pydict<'py>(py: Python<'py>, object: &JsonObject) -> PyResult<&'py PyDict> {
+    let dict = PyDict::new(py);
+    for (key, value) in object {
+        dict.set_item(key, json_to_pyobject(py, value))?;
+    }
+    Ok(dict)
+}
+
+/// Convert what a callable produced back to properties, naming the callable
+/// in errors since they otherwise surface far from their cause.
+fn pydict_to_json_object(obj: &PyAny, callable: &str) -> PyResult<JsonObject> {
+    let dict = obj.downcast::<PyDict>().map_err(|_| {
+        PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+            "{} must produce a dict, got '{}'",
+            callable,
+            type_name(obj)
+        ))
+    })?;
+    let mut object = JsonObject::new();
+    for (key, value) in dict {
+        let key = key.extract::<String>().map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                "Property keys produced by {} must be strings, got '{}'",
+                callable,
+                type_name(key)
+            ))
+        })?;
+        let value = pyobject_to_json(value).map_err(|unsupported| {
+            PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                "Unsupported type '{}' in property '{}' produced by {}",
+                type_name(unsupported),
+                key,
+                callable
+            ))
+        })?;
+        object.insert(key, value);
+    }
+    Ok(object)
+}
@@ -0,0 +1,1669 @@
+//! Point clustering engine, vendored from the `supercluster` crate (1.0.16,
+//! MIT License, Copyright (c) 2024 Chargetrip) so the bindings can work with
+//! the per-zoom trees directly instead of only through cloned features.
+
+#![forbid(unsafe_code)]
+
+mod anchor;
+mod kdbush;
+mod persist;
+mod reload;
+mod select;
+mod store;
+
+pub use anchor::Anchor;
+
+use geojson::{feature::Id, Feature, Geometry, JsonObject, Value::Point};
+use kdbush::KDBush;
+use reload::{History, Level};
+pub use select::Selection;
+use store::Records;
+use serde_json::json;
+use std::borrow::Cow;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// An offset index used to access the zoom level value associated with a cluster in the data arrays.
+const OFFSET_ZOOM: usize = 2;
+
+/// An offset index used to access the ID associated with a cluster in the data arrays.
+const OFFSET_ID: usize = 3;
+
+/// An offset index used to access the identifier of the parent cluster of a point in the data arrays.
+const OFFSET_PARENT: usize = 4;
+
+/// An offset index used to access the number of points contained within a cluster at the given zoom level in the data arrays.
+const OFFSET_NUM: usize = 5;
+
+/// An offset index used to access the properties associated with a cluster in the data arrays.
+const OFFSET_PROP: usize = 6;
+
+/// The largest `Options::max_zoom`, as cluster IDs encode the zoom level they originate from in 5 bits.
+pub const MAX_ZOOM: u8 = 30;
+
+/// Supercluster configuration options.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Minimal zoom level to generate clusters on.
+    pub min_zoom: u8,
+
+    /// Maximal zoom level to cluster the points on, at most `MAX_ZOOM`.
+    pub max_zoom: u8,
+
+    /// Minimum points to form a cluster.
+    pub min_points: u8,
+
+    /// Cluster radius in pixels.
+    pub radius: f64,
+
+    /// Cluster radius in pixels from each listed zoom level on, sorted by zoom; zoom levels below
+    /// the first use `radius`.
+    pub radius_schedule: Vec<(u8, f64)>,
+
+    /// Numeric point property overriding the cluster radius in pixels of the points having it, at
+    /// every zoom level. Clusters take the largest radius of their points, counting points without
+    /// one at the radius of the zoom level they are clustered at.
+    pub radius_property: Option<String>,
+
+    /// Tile extent (radius is calculated relative to it).
+    pub extent: f64,
+
+    /// Size of the KD-tree leaf node, affects performance.
+    pub node_size: usize,
+
+    /// Hooks computing custom cluster properties, if any.
+    pub reducer: Option<Arc<dyn Reducer>>,
+
+    /// Numeric point property weighting cluster centers; points without it weigh 1.
+    pub weight_property: Option<String>,
+
+    /// Cluster points in a canonical order rather than the input order, so that clusters and
+    /// their IDs only depend on the set of points.
+    pub stable_ids: bool,
+
+    /// How point coordinates map to the tiled world.
+    pub coordinate_system: CoordinateSystem,
+
+    /// The point non-point geometries are clustered at, which are skipped if `None`. Tiles have
+    /// them as that point.
+    pub geometry_anchor: Option<Anchor>,
+}
+
+impl Default for Options {
+    /// The defaults of the JavaScript supercluster.
+    fn default() -> Self {
+        Options {
+            min_zoom: 0,
+            max_zoom: 16,
+            min_points: 2,
+            radius: 40.0,
+            radius_schedule: vec![],
+            radius_property: None,
+            extent: 512.0,
+            node_size: 64,
+            reducer: None,
+            weight_property: None,
+            stable_ids: false,
+            coordinate_system: CoordinateSystem::Geographic,
+            geometry_anchor: None,
+        }
+    }
+}
+
+/// How point coordinates map to the unit square the index and its tiles cover.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CoordinateSystem {
+    /// Longitude and latitude in degrees, in the Web Mercator projection, wrapping around the
+    /// antimeridian. Longitudes beyond it are wrapped into the [-180..180] range.
+    Geographic,
+
+    /// Planar coordinates with y pointing up. The world is the square with the top left corner of
+    /// `[min_x, min_y, max_x, max_y]` which fits the bounds.
+    Cartesian { bounds: [f64; 4] },
+}
+
+impl CoordinateSystem {
+    /// Project point coordinates to the [0..1] range, or beyond it for points outside the world.
+    ///
+    /// # Arguments
+    ///
+    /// - `x`: The longitude or x coordinate.
+    /// - `y`: The latitude or y coordinate.
+    ///
+    /// # Returns
+    ///
+    /// The projected coordinates.
+    fn project(self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            CoordinateSystem::Geographic => (lng_x(wrap_lng(x)), lat_y(y)),
+            CoordinateSystem::Cartesian { bounds } => {
+                let size = world_size(bounds);
+                ((x - bounds[0]) / size, (bounds[3] - y) / size)
+            }
+        }
+    }
+
+    /// Convert projected coordinates back to point coordinates.
+    ///
+    /// # Arguments
+    ///
+    /// - `x`: The projected x coordinate.
+    /// - `y`: The projected y coordinate.
+    ///
+    /// # Returns
+    ///
+    /// The longitude and latitude, or the x and y coordinates.
+    fn unproject(self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            CoordinateSystem::Geographic => (x_lng(x), y_lat(y)),
+            CoordinateSystem::Cartesian { bounds } => {
+                let size = world_size(bounds);
+                (bounds[0] + x * size, bounds[3] - y * size)
+            }
+        }
+    }
+}
+
+/// The side of the square world fitting Cartesian bounds.
+fn world_size(bounds: [f64; 4]) -> f64 {
+    (bounds[2] - bounds[0]).max(bounds[3] - bounds[1])
+}
+
+/// An error raised by a `Reducer`, which aborts `Supercluster::load`.
+pub type ReduceError = Box<dyn Error + Send + Sync>;
+
+/// Custom cluster properties, computed like the `map` and `reduce` options of the JavaScript supercluster.
+pub trait Reducer: Debug + Send + Sync {
+    /// Map the properties of an input point to the properties it contributes to its clusters.
+    ///
+    /// # Arguments
+    ///
+    /// - `properties`: The properties of the input point.
+    ///
+    /// # Returns
+    ///
+    /// The mapped properties, or the error to abort loading with.
+    fn map(&self, properties: &JsonObject) -> Result<JsonObject, ReduceError>;
+
+    /// Merge the mapped properties of a point, or the properties of a cluster, into the properties of
+    /// a cluster being built.
+    ///
+    /// # Arguments
+    ///
+    /// - `accumulated`: The properties of the cluster being built.
+    /// - `properties`: The properties to merge into it.
+    ///
+    /// # Returns
+    ///
+    /// The error to abort loading with, if any.
+    fn reduce(&self, accumulated: &mut JsonObject, properties: &JsonObject) -> Result<(), ReduceError>;
+
+    /// Turn the accumulated properties of a cluster into the properties it is returned with,
+    /// once all zoom levels are built.
+    ///
+    /// # Arguments
+    ///
+    /// - `accumulated`: The properties of the cluster.
+    fn finish(&self, _accumulated: &mut JsonObject) {}
+}
+
+/// A cluster or an unclustered point, as returned by `Selected::get_cluster_points`.
+#[derive(Clone, Debug)]
+pub struct ClusterPoint {
+    /// Longitude of the cluster center or of the point.
+    pub lng: f64,
+
+    /// Latitude of the cluster center or of the point.
+    pub lat: f64,
+
+    /// Cluster ID, `None` for unclustered points.
+    pub cluster_id: Option<usize>,
+
+    /// Number of points in the cluster, 1 for unclustered points.
+    pub point_count: usize,
+
+    /// Index of the point in the loaded features, `None` for clusters.
+    pub point_index: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+/// A spatial clustering configuration and data structure.
+pub struct Supercluster {
+    /// Configuration settings.
+    options: Options,
+
+    /// Vector of KDBush structures for different zoom levels.
+    trees: Vec<KDBush>,
+
+    /// Stride used for data access within the KD-tree.
+    stride: usize,
+
+    /// Offset of the point or cluster weight in the data arrays, if points are weighted.
+    offset_weight: Option<usize>,
+
+    /// Offset of the point or cluster radius in the data arrays, if points have their own radius.
+    offset_radius: Option<usize>,
+
+    /// Largest radius in pixels of the points having their own, 0 if none do.
+    max_point_radius: f64,
+
+    /// Input data points.
+    pub points: Records<Feature>,
+
+    /// Clusters metadata.
+    cluster_props: Records<JsonObject>,
+
+    /// How the points were clustered, which `Supercluster::reload` reuses; `None` for indexes that
+    /// were deserialized rather than loaded.
+    history: Option<History>,
+}
+
+impl Supercluster {
+    /// Create a new instance of `Supercluster` with the specified configuration settings.
+    ///
+    /// # Arguments
+    ///
+    /// - `options`: The configuration options for Supercluster.
+    ///
+    /// # Returns
+    ///
+    /// A new `Supercluster` instance with the given configuration.
+    pub fn new(options: Options) -> Self {
+        let capacity = options.max_zoom + 1;
+        let trees: Vec<KDBush> = (0..capacity + 1)
+            .map(|_| KDBush::new(0, options.node_size))
+            .collect();
+
+        // Clusters keep an index into the cluster properties when they are computed,
+        // followed by their total weight when points are weighted and their radius
+        // when points have their own
+        let mut stride = if options.reducer.is_some() { 7 } else { 6 };
+        let offset_weight = options.weight_property.as_ref().map(|_| {
+            stride += 1;
+            stride - 1
+        });
+        let offset_radius = options.radius_property.as_ref().map(|_| {
+            stride += 1;
+            stride - 1
+        });
+
+        Supercluster {
+            trees,
+            options,
+            stride,
+            offset_weight,
+            offset_radius,
+            max_point_radius: 0.0,
+            points: vec![].into(),
+            cluster_props: vec![].into(),
+            history: None,
+        }
+    }
+
+    /// The configuration settings of the instance.
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Load the FeatureCollection Object into the Supercluster instance, performing clustering at various zoom levels.
+    ///
+    /// # Arguments
+    ///
+    /// - `points`: A vector of GeoJSON features representing input points to be clustered.
+    ///
+    /// # Returns
+    ///
+    /// A mutable reference to the updated `Supercluster` instance, or the error raised by the reducer,
+    /// in which case the instance is left partially loaded.
+    pub fn load(&mut self, points: Vec<Feature>) -> Result<&mut Self, ReduceError> {
+        let data = self.point_data(&points);
+
+        self.index_points(points, data, None)
+    }
+
+    /// Find the data offsets of the clusters and points within the specified bounding box and zoom level.
+    /// Bounding boxes crossing the antimeridian (`min_lng > max_lng` once normalized) are split in two,
+    /// latitudes beyond the Web Mercator range project onto its edges like the points there, and
+    /// longitude spans of 360° or more cover the whole world.
+    ///
+    /// # Arguments
+    ///
+    /// - `bbox`: The bounding box as an array of four coordinates [min_lng, min_lat, max_lng, max_lat].
+    /// - `zoom`: The zoom level at which to retrieve clusters.
+    ///
+    /// # Returns
+    ///
+    /// A vector of offsets into the data array of the tree for the (limited) zoom level.
+    fn range_offsets(&self, bbox: [f64; 4], zoom: u8) -> Vec<usize> {
+        let tree = &self.trees[self.limit_zoom(zoom)];
+
+        if let CoordinateSystem::Cartesian { .. } = self.options.coordinate_system {
+            let (min_x, min_y) = self.options.coordinate_system.project(bbox[0], bbox[3]);
+            let (max_x, max_y) = self.options.coordinate_system.project(bbox[2], bbox[1]);
+            let ids = tree.range(min_x, min_y, max_x, max_y);
+
+            return ids.into_iter().map(|id| self.stride * id).collect();
+        }
+
+        let mut min_lng = ((((bbox[0] + 180.0) % 360.0) + 360.0) % 360.0) - 180.0;
+        let min_lat = bbox[1];
+        let mut max_lng = if bbox[2] == 180.0 {
+            180.0
+        } else {
+            ((((bbox[2] + 180.0) % 360.0) + 360.0) % 360.0) - 180.0
+        };
+        let max_lat = bbox[3];
+
+        if bbox[2] - bbox[0] >= 360.0 {
+            min_lng = -180.0;
+            max_lng = 180.0;
+        } else if min_lng > max_lng {
+            let eastern_hem = self.range_offsets([min_lng, min_lat, 180.0, max_lat], zoom);
+            let western_hem = self.range_offsets([-180.0, min_lat, max_lng, max_lat], zoom);
+
+            return eastern_hem.into_iter().chain(western_hem).collect();
+        }
+
+        let ids = tree.range(
+            lng_x(min_lng),
+            lat_y(max_lat),
+            lng_x(max_lng),
+            lat_y(min_lat),
+        );
+
+        ids.into_iter().map(|id| self.stride * id).collect()
+    }
+
+    /// Create a KD-tree using the specified data, which is used for spatial indexing.
+    ///
+    /// # Arguments
+    ///
+    /// - `data`: A vector of flat numeric arrays representing point data for the KD-tree.
+    ///
+    /// # Returns
+    ///
+    /// A `KDBush` instance with the specified data.
+    fn create_tree(&mut self, data: Vec<f64>) -> KDBush {
+        let mut tree = KDBush::new(data.len() / self.stride, self.options.node_size);
+
+        for i in (0..data.len()).step_by(self.stride) {
+            tree.add_point(data[i], data[i + 1]);
+        }
+
+        tree.build_index();
+        tree.data = data.into();
+
+        tree
+    }
+
+    /// Calculate the effective zoom level that takes into account the configured minimum and maximum zoom levels.
+    ///
+    /// # Arguments
+    ///
+    /// - `zoom`: The initial zoom level.
+    ///
+    /// # Returns
+    ///
+    /// The effective zoom level considering the configured minimum and maximum zoom levels.
+    fn limit_zoom(&self, zoom: u8) -> usize {
+        zoom.max(self.options.min_zoom)
+            .min(self.options.max_zoom + 1) as usize
+    }
+
+    /// Store the input points in flat numeric arrays, skipping those without a point to cluster them at.
+    ///
+    /// # Arguments
+    ///
+    /// - `points`: The input points.
+    ///
+    /// # Returns
+    ///
+    /// The data arrays of the points, in the order they are clustered in.
+    fn point_data(&self, points: &[Feature]) -> Vec<f64> {
+        let mut data = vec![];
+
+        for (i, feature) in points.iter().enumerate() {
+            // Store internal point/cluster data in flat numeric arrays for performance
+            let coordinates = match feature.geometry.as_ref().map(|g| &g.value) {
+                Some(Point(coords)) => [coords[0], coords[1]],
+                Some(value) => match self.options.geometry_anchor.and_then(|anchor| anchor.point(value)) {
+                    Some(coords) => coords,
+                    None => continue,
+                },
+                None => continue,
+            };
+
+            // Projected longitude and latitude
+            let (x, y) = self.options.coordinate_system.project(coordinates[0], coordinates[1]);
+            data.push(x);
+            data.push(y);
+
+            // The last zoom the point was processed at
+            data.push(f64::INFINITY);
+
+            // Index of the source feature in the original input array
+            data.push(i as f64);
+
+            // Parent cluster id
+            data.push(-1.0);
+
+            // Number of points in a cluster
+            data.push(1.0);
+
+            // Index of the cluster properties, unused for points
+            if self.options.reducer.is_some() {
+                data.push(-1.0);
+            }
+
+            // Weight of the point
+            if let Some(weight_property) = &self.options.weight_property {
+                data.push(
+                    feature
+                        .property(weight_property)
+                        .and_then(|w| w.as_f64())
+                        .unwrap_or(1.0),
+                );
+            }
+
+            // Radius of the point, NaN to use the radius of the zoom level
+            if let Some(radius_property) = &self.options.radius_property {
+                data.push(
+                    feature
+                        .property(radius_property)
+                        .and_then(|r| r.as_f64())
+                        .unwrap_or(f64::NAN),
+                );
+            }
+        }
+
+        if self.options.stable_ids {
+            data = self.sort_canonically(points, data);
+        }
+
+        data
+    }
+
+    /// Index the input points and cluster them at every zoom level.
+    ///
+    /// # Arguments
+    ///
+    /// - `points`: The input points.
+    /// - `data`: The data arrays of the points, as returned by `point_data`.
+    /// - `previous`: An index of similar points along with the positions of its point data matching
+    ///   `data`, whose clustering is reused where the points did not change.
+    ///
+    /// # Returns
+    ///
+    /// A mutable reference to the updated `Supercluster` instance, or the error raised by the reducer.
+    fn index_points(
+        &mut self,
+        points: Vec<Feature>,
+        data: Vec<f64>,
+        mut previous: Option<(&Supercluster, Vec<Option<u32>>)>,
+    ) -> Result<&mut Self, ReduceError> {
+        let min_zoom = self.options.min_zoom;
+        let max_zoom = self.options.max_zoom;
+
+        self.cluster_props = vec![].into();
+        self.history = None;
+        self.points = points.into();
+        self.trees[(max_zoom as usize) + 1] = self.create_tree(data);
+        self.max_point_radius = self.compute_max_point_radius();
+
+        let mut cluster_props = vec![];
+        let mut handlers = vec![vec![]; self.trees.len()];
+
+        // Cluster points on max zoom, then cluster the results on previous zoom, etc.;
+        // Results in a cluster hierarchy across zoom levels
+        for zoom in (min_zoom..=max_zoom).rev() {
+            let tree = &self.trees[(zoom as usize) + 1];
+            let mut level = previous
+                .take()
+                .and_then(|(index, matches)| Level::new(self, index, zoom, &tree.data, matches));
+
+            // Create a new set of clusters for the zoom and index them with a KD-tree
+            let (data, current, visits) = self.cluster(tree, zoom, &mut cluster_props, level.as_mut())?;
+            previous = level.map(|level| level.into_output_matches(current.len() / self.stride));
+
+            handlers[(zoom as usize) + 1] = visits;
+            self.trees[(zoom as usize) + 1].data = data.into();
+            self.trees[zoom as usize] = self.create_tree(current);
+        }
+
+        // Keep the properties as they were before being finished for the clusters reused by reload
+        let accumulated = match &self.options.reducer {
+            Some(reducer) => {
+                let accumulated = cluster_props.clone();
+                for properties in &mut cluster_props {
+                    reducer.finish(properties);
+                }
+                accumulated
+            }
+            None => vec![],
+        };
+
+        self.cluster_props = cluster_props.into();
+        self.history = Some(History { handlers, accumulated });
+
+        Ok(self)
+    }
+
+    /// Reorder the data of input points by their coordinates, ties broken by their ID and properties,
+    /// so that clustering does not depend on the input order.
+    ///
+    /// # Arguments
+    ///
+    /// - `points`: The input points the data refers to.
+    /// - `data`: The flat numeric arrays representing the input points.
+    ///
+    /// # Returns
+    ///
+    /// The data in canonical order.
+    fn sort_canonically(&self, points: &[Feature], data: Vec<f64>) -> Vec<f64> {
+        let tie_key = |k: usize| {
+            let point = &points[data[k + OFFSET_ID] as usize];
+            let id = point.id.as_ref().map(|id| json!(id).to_string());
+            let properties = point.properties.as_ref().map(|p| json!(p).to_string());
+            (id, properties)
+        };
+
+        let mut offsets: Vec<usize> = (0..data.len()).step_by(self.stride).collect();
+        offsets.sort_by(|&a, &b| {
+            data[a]
+                .total_cmp(&data[b])
+                .then(data[a + 1].total_cmp(&data[b + 1]))
+                .then_with(|| tie_key(a).cmp(&tie_key(b)))
+        });
+
+        offsets
+            .into_iter()
+            .flat_map(|k| data[k..k + self.stride].iter().copied())
+            .collect()
+    }
+
+    /// Cluster points on a given zoom level using a KD-tree and returns updated data arrays.
+    ///
+    /// # Arguments
+    ///
+    /// - `tree`: A reference to the KD-tree structure for spatial indexing.
+    /// - `zoom`: The zoom level at which clustering is performed.
+    /// - `cluster_props`: The cluster properties computed so far, extended with those of the new clusters.
+    /// - `level`: The clustering of a previous index at the zoom level, reused where possible.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of three vectors: the first one contains updated data arrays for the current zoom level,
+    /// the second one contains data arrays for the next zoom level, and the third one the position of
+    /// the point or cluster each point or cluster was processed with; or the error raised by the reducer.
+    #[allow(clippy::type_complexity)]
+    fn cluster(
+        &self,
+        tree: &KDBush,
+        zoom: u8,
+        cluster_props: &mut Vec<JsonObject>,
+        mut level: Option<&mut Level>,
+    ) -> Result<(Vec<f64>, Vec<f64>, Vec<u32>), ReduceError> {
+        let reducer = self.options.reducer.as_deref();
+        let scale = self.options.extent * (2.0_f64).powi(zoom as i32);
+        let mut data = tree.data.to_vec();
+        let mut next_data = Vec::new();
+        let mut handlers = vec![0; data.len() / self.stride];
+
+        // Loop through each point
+        for i in (0..data.len()).step_by(self.stride) {
+            // If we've already visited the point at this zoom level, skip it
+            if data[i + OFFSET_ZOOM] <= (zoom as f64) {
+                continue;
+            }
+
+            data[i + OFFSET_ZOOM] = zoom as f64;
+
+            let handler = i / self.stride;
+            handlers[handler] = handler as u32;
+
+            // Find all nearby points, in the order of the data so that it does not depend on the tree,
+            // unless the previous index tells which ones were merged or left as they are
+            let x = data[i];
+            let y = data[i + 1];
+
+            let reused = level
+                .as_mut()
+                .and_then(|level| level.reuse(self, &data, handler, next_data.len() / self.stride));
+            let fresh = reused.is_none();
+            let (neighbor_ids, mut reused_properties) = reused.unwrap_or_else(|| {
+                let mut neighbor_ids = tree.within(x, y, self.radius(&data, i, zoom) / scale);
+                neighbor_ids.sort_unstable();
+                (neighbor_ids, None)
+            });
+
+            let num_points_origin = data[i + OFFSET_NUM];
+            let mut num_points = num_points_origin;
+
+            // Count the number of points in a potential cluster
+            for neighbor_id in &neighbor_ids {
+                let k = neighbor_id * self.stride;
+
+                // Filter out neighbors that are already processed
+                if data[k + OFFSET_ZOOM] > (zoom as f64) {
+                    num_points += data[k + OFFSET_NUM];
+                }
+            }
+
+            // If there were neighbors to merge, and there are enough points to form a cluster
+            if num_points > num_points_origin && num_points >= (self.options.min_points as f64) {
+                let mut weight = self.weight(&data, i);
+                let mut radius = self.offset_radius.map(|offset| data[i + offset]);
+                let mut without_radius = radius.is_some_and(f64::is_nan);
+                let mut wx = x * weight;
+                let mut wy = y * weight;
+
+                // Encode both zoom and point index on which the cluster originated -- offset by total length of features
+                let id = (handler << 5) + ((zoom as usize) + 1) + self.points.len();
+
+                let mut properties = match (reducer, reused_properties.take()) {
+                    (Some(_), Some(properties)) => Some(properties),
+                    (Some(reducer), None) => Some(
+                        self.map_properties(reducer, &data, i, cluster_props)?
+                            .into_owned(),
+                    ),
+                    (None, _) => None,
+                };
+
+                for &neighbor_id in &neighbor_ids {
+                    let k = neighbor_id * self.stride;
+
+                    if data[k + OFFSET_ZOOM] <= (zoom as f64) {
+                        continue;
+                    }
+
+                    // Save the zoom (so it doesn't get processed twice)
+                    data[k + OFFSET_ZOOM] = zoom as f64;
+                    handlers[neighbor_id] = handler as u32;
+
+                    let weight2 = self.weight(&data, k);
+
+                    // Accumulate coordinates for calculating weighted center
+                    wx += data[k] * weight2;
+                    wy += data[k + 1] * weight2;
+                    weight += weight2;
+
+                    if let (Some(offset), Some(radius)) = (self.offset_radius, radius.as_mut()) {
+                        without_radius |= data[k + offset].is_nan();
+                        *radius = radius.max(data[k + offset]);
+                    }
+
+                    // Reused properties already have those of the neighbors
+                    if let (Some(reducer), Some(properties), true) = (reducer, properties.as_mut(), fresh) {
+                        let mapped = self.map_properties(reducer, &data, k, cluster_props)?;
+                        reducer.reduce(properties, &mapped)?;
+                    }
+
+                    data[k + OFFSET_PARENT] = id as f64;
+                }
+
+                data[i + OFFSET_PARENT] = id as f64;
+
+                next_data.push(wx / weight);
+                next_data.push(wy / weight);
+                next_data.push(f64::INFINITY);
+                next_data.push(id as f64);
+                next_data.push(-1.0);
+                next_data.push(num_points);
+
+                if let Some(properties) = properties {
+                    next_data.push(cluster_props.len() as f64);
+                    cluster_props.push(properties);
+                }
+
+                if self.offset_weight.is_some() {
+                    next_data.push(weight);
+                }
+
+                if let Some(radius) = radius {
+                    // Points without a radius of their own count with the radius of the zoom level,
+                    // the cluster keeps using that if none of its points has one
+                    if without_radius && !radius.is_nan() {
+                        next_data.push(radius.max(self.radius_at(zoom)));
+                    } else {
+                        next_data.push(radius);
+                    }
+                }
+            } else {
+                // Left points as unclustered
+                for j in 0..self.stride {
+                    next_data.push(data[i + j]);
+                }
+
+                if num_points > 1.0 {
+                    for &neighbor_id in &neighbor_ids {
+                        let k = neighbor_id * self.stride;
+
+                        if data[k + OFFSET_ZOOM] <= (zoom as f64) {
+                            continue;
+                        }
+
+                        data[k + OFFSET_ZOOM] = zoom as f64;
+                        handlers[neighbor_id] = handler as u32;
+
+                        for j in 0..self.stride {
+                            next_data.push(data[k + j]);
+                        }
+                    }
+                }
+            }
+
+            if let (Some(level), true) = (level.as_mut(), fresh) {
+                let visited = neighbor_ids.iter().copied().filter(|&k| handlers[k] == handler as u32);
+                level.processed(handler, visited);
+            }
+        }
+
+        Ok((data, next_data, handlers))
+    }
+
+    /// Get the weight of a point or cluster, which is its number of points unless points are weighted.
+    ///
+    /// # Arguments
+    ///
+    /// - `data`: A reference to the flat numeric arrays representing point data.
+    /// - `i`: The index in the data array for the point or cluster.
+    ///
+    /// # Returns
+    ///
+    /// The weight of the point or cluster.
+    fn weight(&self, data: &[f64], i: usize) -> f64 {
+        match self.offset_weight {
+            Some(offset) => data[i + offset],
+            None => data[i + OFFSET_NUM],
+        }
+    }
+
+    /// Get the cluster radius in pixels at a zoom level, before any radius of the points.
+    ///
+    /// # Arguments
+    ///
+    /// - `zoom`: The zoom level.
+    ///
+    /// # Returns
+    ///
+    /// The radius of the last scheduled zoom level up to `zoom`, or `radius`.
+    fn radius_at(&self, zoom: u8) -> f64 {
+        self.options
+            .radius_schedule
+            .iter()
+            .rev()
+            .find(|&&(from, _)| from <= zoom)
+            .map_or(self.options.radius, |&(_, radius)| radius)
+    }
+
+    /// Get the largest radius in pixels of the points having their own, which clusters take theirs from.
+    ///
+    /// # Returns
+    ///
+    /// The largest radius in the data of the input points, or 0 if no point has one.
+    fn compute_max_point_radius(&self) -> f64 {
+        match (self.offset_radius, self.trees.last()) {
+            (Some(offset), Some(tree)) => tree
+                .data
+                .chunks_exact(self.stride)
+                .map(|point| point[offset])
+                .fold(0.0, f64::max),
+            _ => 0.0,
+        }
+    }
+
+    /// Get the cluster radius in pixels of a point or cluster, its own radius if it has one.
+    ///
+    /// # Arguments
+    ///
+    /// - `data`: A reference to the flat numeric arrays representing point data.
+    /// - `i`: The index in the data array for the point or cluster.
+    /// - `zoom`: The zoom level it is clustered at.
+    ///
+    /// # Returns
+    ///
+    /// The radius of the point or cluster.
+    fn radius(&self, data: &[f64], i: usize, zoom: u8) -> f64 {
+        match self.offset_radius.map(|offset| data[i + offset]) {
+            Some(radius) if !radius.is_nan() => radius,
+            _ => self.radius_at(zoom),
+        }
+    }
+
+    /// Get the properties a point or cluster contributes to the cluster it is merged into.
+    ///
+    /// # Arguments
+    ///
+    /// - `reducer`: The hooks computing custom cluster properties.
+    /// - `data`: A reference to the flat numeric arrays representing point data.
+    /// - `i`: The index in the data array for the point or cluster.
+    /// - `cluster_props`: A reference to a vector of cluster properties.
+    ///
+    /// # Returns
+    ///
+    /// The mapped properties of a point, or the accumulated properties of a cluster; or the error
+    /// raised by the reducer.
+    fn map_properties<'a>(
+        &self,
+        reducer: &dyn Reducer,
+        data: &[f64],
+        i: usize,
+        cluster_props: &'a [JsonObject],
+    ) -> Result<Cow<'a, JsonObject>, ReduceError> {
+        if data[i + OFFSET_NUM] > 1.0 {
+            return Ok(Cow::Borrowed(&cluster_props[data[i + OFFSET_PROP] as usize]));
+        }
+
+        let empty = JsonObject::new();
+        let point = self.points.get(data[i + OFFSET_ID] as usize);
+        let properties = point.properties.as_ref().unwrap_or(&empty);
+
+        Ok(Cow::Owned(reducer.map(properties)?))
+    }
+
+    /// Decode a cluster ID into the zoom level the cluster was formed at and the input point it
+    /// originated from.
+    ///
+    /// # Arguments
+    ///
+    /// - `cluster_id`: The unique identifier of the cluster.
+    ///
+    /// # Returns
+    ///
+    /// The zoom level and the index of the origin point in the loaded features, or `None` if no cluster
+    /// of this index has the ID.
+    pub fn decode_cluster_id(&self, cluster_id: usize) -> Option<(u8, usize)> {
+        let offset = cluster_id.checked_sub(self.points.len())?;
+        let origin_id = offset >> 5;
+        let origin_zoom = offset % 32;
+
+        // Clusters formed at a zoom level originate from the tree of the next one
+        if origin_zoom <= self.options.min_zoom as usize || origin_zoom > self.options.max_zoom as usize + 1 {
+            return None;
+        }
+
+        let data = &self.trees[origin_zoom].data;
+        let k = origin_id.checked_mul(self.stride)?;
+        if k >= data.len() || data[k + OFFSET_PARENT] != cluster_id as f64 {
+            return None;
+        }
+
+        let origin_point = if data[k + OFFSET_NUM] > 1.0 {
+            self.decode_cluster_id(data[k + OFFSET_ID] as usize)?.1
+        } else {
+            data[k + OFFSET_ID] as usize
+        };
+
+        Some(((origin_zoom - 1) as u8, origin_point))
+    }
+
+    /// Get the index of the point from which the cluster originated.
+    ///
+    /// # Arguments
+    ///
+    /// - `cluster_id`: The unique identifier of the cluster.
+    ///
+    /// # Returns
+    ///
+    /// The index of the point from which the cluster originated.
+    fn get_origin_id(&self, cluster_id: usize) -> usize {
+        (cluster_id - self.points.len()) >> 5
+    }
+
+    /// Get the zoom of the point from which the cluster originated.
+    ///
+    /// # Arguments
+    ///
+    /// - `cluster_id`: The unique identifier of the cluster.
+    ///
+    /// # Returns
+    ///
+    /// The zoom level of the point from which the cluster originated.
+    fn get_origin_zoom(&self, cluster_id: usize) -> usize {
+        (cluster_id - self.points.len()) % 32
+    }
+}
+
+/// Convert clustered point data into a GeoJSON feature representing a cluster.
+///
+/// # Arguments
+///
+/// - `data`: A reference to the flat numeric arrays representing point data.
+/// - `i`: The index in the data array for the cluster.
+/// - `cluster_props`: A reference to a vector of cluster properties.
+/// - `offset_weight`: The offset of the cluster weight in the data arrays, if points are weighted.
+/// - `coordinate_system`: How the cluster center is converted back to point coordinates.
+///
+/// # Returns
+///
+/// A GeoJSON feature representing a cluster.
+fn get_cluster_json(
+    data: &[f64],
+    i: usize,
+    cluster_props: &Records<JsonObject>,
+    offset_weight: Option<usize>,
+    coordinate_system: CoordinateSystem,
+) -> Feature {
+    let (x, y) = coordinate_system.unproject(data[i], data[i + 1]);
+    let geometry = Geometry::new(Point(vec![x, y]));
+
+    Feature {
+        id: Some(Id::String(data[i + OFFSET_ID].to_string())),
+        bbox: None,
+        foreign_members: None,
+        geometry: Some(geometry),
+        properties: Some(get_cluster_properties(data, i, cluster_props, offset_weight)),
+    }
+}
+
+/// Retrieve properties for a cluster based on clustered point data.
+///
+/// # Arguments
+///
+/// - `data`: A reference to the flat numeric arrays representing point data.
+/// - `i`: The index in the data array for the cluster.
+/// - `cluster_props`: A reference to a vector of cluster properties.
+/// - `offset_weight`: The offset of the cluster weight in the data arrays, if points are weighted.
+///
+/// # Returns
+///
+/// Properties for the cluster based on the clustered point data.
+fn get_cluster_properties(
+    data: &[f64],
+    i: usize,
+    cluster_props: &Records<JsonObject>,
+    offset_weight: Option<usize>,
+) -> JsonObject {
+    let count = data[i + OFFSET_NUM];
+    let abbrev = if count >= 10000.0 {
+        format!("{}k", count / 1000.0)
+    } else if count >= 1000.0 {
+        format!("{:}k", count / 100.0 / 10.0)
+    } else {
+        count.to_string()
+    };
+
+    let mut properties = if !cluster_props.is_empty() && data.get(i + OFFSET_PROP).is_some() {
+        cluster_props.get(data[i + OFFSET_PROP] as usize).into_owned()
+    } else {
+        JsonObject::new()
+    };
+
+    properties.insert("cluster".to_string(), json!(true));
+    properties.insert(
+        "cluster_id".to_string(),
+        json!(data[i + OFFSET_ID] as usize),
+    );
+    properties.insert("point_count".to_string(), json!(count as usize));
+    properties.insert("point_count_abbreviated".to_string(), json!(abbrev));
+
+    if let Some(offset) = offset_weight {
+        properties.insert("weight_sum".to_string(), json!(data[i + offset]));
+    }
+
+    properties
+}
+
+/// Convert longitude to spherical mercator in the [0..1] range.
+///
+/// # Arguments
+///
+/// - `lng`: The longitude value to be converted.
+///
+/// # Returns
+///
+/// The converted value in the [0..1] range.
+fn lng_x(lng: f64) -> f64 {
+    lng / 360.0 + 0.5
+}
+
+/// Bring a longitude beyond the antimeridian back into the [-180..180] range.
+///
+/// # Arguments
+///
+/// - `lng`: The longitude value to be wrapped.
+///
+/// # Returns
+///
+/// The same longitude in the [-180..180] range.
+fn wrap_lng(lng: f64) -> f64 {
+    if (-180.0..=180.0).contains(&lng) {
+        lng
+    } else {
+        ((((lng + 180.0) % 360.0) + 360.0) % 360.0) - 180.0
+    }
+}
+
+/// Convert latitude to spherical mercator in the [0..1] range.
+///
+/// # Arguments
+///
+/// - `lat`: The latitude value to be converted, which is clamped to the poles.
+///
+/// # Returns
+///
+/// The converted value in the [0..1] range, whose edges latitudes beyond the Web Mercator range
+/// project onto.
+fn lat_y(lat: f64) -> f64 {
+    let sin = lat.clamp(-90.0, 90.0).to_radians().sin();
+    let y = 0.5 - (0.25 * ((1.0 + sin) / (1.0 - sin)).ln()) / PI;
+
+    y.clamp(0.0, 1.0)
+}
+
+/// Convert spherical mercator to longitude.
+///
+/// # Arguments
+///
+/// - `x`: The spherical mercator value to be converted.
+///
+/// # Returns
+///
+/// The converted longitude value.
+fn x_lng(x: f64) -> f64 {
+    (x - 0.5) * 360.0
+}
+
+/// Convert spherical mercator to latitude.
+///
+/// # Arguments
+///
+/// - `y`: The spherical mercator value to be converted.
+///
+/// # Returns
+///
+/// The converted latitude value.
+fn y_lat(y: f64) -> f64 {
+    let y2 = ((180.0 - y * 360.0) * PI) / 180.0;
+    (360.0 * y2.exp().atan()) / PI - 90.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The latitude limit of the Web Mercator projection, rounded up so that it projects onto the edge.
+    const MAX_LATITUDE: f64 = 85.051_128_779_806_6;
+
+    fn setup() -> Supercluster {
+        Supercluster::new(Options::default())
+    }
+
+    fn load_points(coordinates: &[[f64; 2]]) -> Supercluster {
+        let mut supercluster = setup();
+        let points = coordinates
+            .iter()
+            .map(|&[lng, lat]| Feature {
+                geometry: Some(Geometry::new(Point(vec![lng, lat]))),
+                properties: Some(JsonObject::new()),
+                ..Default::default()
+            })
+            .collect();
+
+        supercluster.load(points).unwrap();
+        supercluster
+    }
+
+    fn longitudes(features: &[Feature]) -> Vec<f64> {
+        let mut longitudes: Vec<f64> = features
+            .iter()
+            .map(|feature| match &feature.geometry.as_ref().unwrap().value {
+                Point(coords) => coords[0],
+                _ => panic!("expected a point"),
+            })
+            .collect();
+
+        longitudes.sort_by(f64::total_cmp);
+        longitudes
+    }
+
+    #[test]
+    fn test_get_clusters_crossing_antimeridian() {
+        let supercluster = load_points(&[[175.0, 0.0], [-175.0, 0.0], [0.0, 0.0], [150.0, 0.0]]);
+
+        let crossing = supercluster.get_clusters([170.0, -10.0, -170.0, 10.0], 16);
+        assert_eq!(longitudes(&crossing), vec![-175.0, 175.0]);
+
+        // The same box with its eastern edge past 180°
+        let unwrapped = supercluster.get_clusters([170.0, -10.0, 190.0, 10.0], 16);
+        assert_eq!(longitudes(&unwrapped), vec![-175.0, 175.0]);
+
+        // And with its western edge before -180°
+        let unwrapped = supercluster.get_clusters([-190.0, -10.0, -170.0, 10.0], 16);
+        assert_eq!(longitudes(&unwrapped), vec![-175.0, 175.0]);
+    }
+
+    #[test]
+    fn test_get_clusters_crossing_antimeridian_merges_halves() {
+        let supercluster = load_points(&[[179.0, 1.0], [-179.0, -1.0], [-100.0, 0.0], [100.0, 0.0]]);
+
+        for zoom in 0..=16 {
+            let crossing = supercluster.get_clusters([120.0, -20.0, -120.0, 20.0], zoom);
+            let mut halves = supercluster.get_clusters([120.0, -20.0, 180.0, 20.0], zoom);
+            halves.extend(supercluster.get_clusters([-180.0, -20.0, -120.0, 20.0], zoom));
+
+            assert_eq!(longitudes(&crossing), longitudes(&halves));
+        }
+    }
+
+    #[test]
+    fn test_get_clusters_crossing_antimeridian_without_duplicates() {
+        // Points on either side of the antimeridian, on it, and given beyond it
+        let supercluster = load_points(&[
+            [179.0, 0.0],
+            [-179.0, 0.0],
+            [180.0, 1.0],
+            [-180.0, -1.0],
+            [190.0, 2.0],
+            [-185.0, -2.0],
+            [0.0, 0.0],
+        ]);
+        let crossing = supercluster.get_clusters([170.0, -10.0, -160.0, 10.0], 16);
+        assert_eq!(longitudes(&crossing), vec![-185.0, -180.0, -179.0, 179.0, 180.0, 190.0]);
+
+        let world = supercluster.get_clusters([-180.0, -90.0, 180.0, 90.0], 16);
+        assert_eq!(longitudes(&world), vec![-185.0, -180.0, -179.0, 0.0, 179.0, 180.0, 190.0]);
+
+        // Clusters across the antimeridian take in the wrapped points too, once
+        for zoom in 0..=16 {
+            let crossing = supercluster.get_clusters([170.0, -10.0, -160.0, 10.0], zoom);
+            let count: u64 = crossing
+                .iter()
+                .map(|f| f.property("point_count").and_then(|c| c.as_u64()).unwrap_or(1))
+                .sum();
+            assert_eq!(count, 6, "zoom {}", zoom);
+        }
+    }
+
+    #[test]
+    fn test_get_clusters_beyond_the_poles() {
+        let supercluster = load_points(&[[0.0, 95.0], [10.0, -120.0], [20.0, 86.0], [30.0, 80.0]]);
+
+        // Latitudes beyond the poles project onto the edges rather than back into the world
+        let north = supercluster.get_clusters([-180.0, 86.0, 180.0, 90.0], 16);
+        assert_eq!(longitudes(&north), vec![0.0, 20.0]);
+
+        let south = supercluster.get_clusters([-180.0, -1000.0, 180.0, -86.0], 16);
+        assert_eq!(longitudes(&south), vec![10.0]);
+
+        let everything = supercluster.get_clusters([-180.0, -1000.0, 180.0, 1000.0], 16);
+        assert_eq!(longitudes(&everything), vec![0.0, 10.0, 20.0, 30.0]);
+    }
+
+    #[test]
+    fn test_get_clusters_clamps_latitude() {
+        let supercluster = load_points(&[[0.0, 89.0], [10.0, -89.0], [20.0, 84.0], [30.0, 0.0]]);
+
+        assert_eq!(lat_y(MAX_LATITUDE), 0.0);
+        assert_eq!(lat_y(-MAX_LATITUDE), 1.0);
+
+        // Points beyond the Web Mercator range project onto its edges and are still found
+        let beyond = supercluster.get_clusters([-180.0, -100.0, 180.0, 100.0], 16);
+        assert_eq!(longitudes(&beyond), vec![0.0, 10.0, 20.0, 30.0]);
+
+        let limit = supercluster.get_clusters([-180.0, -MAX_LATITUDE, 180.0, MAX_LATITUDE], 16);
+        assert_eq!(longitudes(&limit), vec![0.0, 10.0, 20.0, 30.0]);
+
+        let north = supercluster.get_clusters([-180.0, 80.0, 180.0, 90.0], 16);
+        assert_eq!(longitudes(&north), vec![0.0, 20.0]);
+    }
+
+    #[test]
+    fn test_get_clusters_whole_world_span() {
+        let supercluster = load_points(&[[-179.0, 0.0], [-60.0, 10.0], [60.0, -10.0], [179.0, 0.0]]);
+        let all = vec![-179.0, -60.0, 60.0, 179.0];
+
+        for bbox in [
+            [-180.0, -90.0, 180.0, 90.0],
+            [0.0, -90.0, 360.0, 90.0],
+            [-200.0, -90.0, 200.0, 90.0],
+            [-540.0, -90.0, 540.0, 90.0],
+        ] {
+            assert_eq!(longitudes(&supercluster.get_clusters(bbox, 16)), all);
+        }
+
+        // Shorter spans do not wrap around
+        let gap = supercluster.get_clusters([-170.0, -90.0, 170.0, 90.0], 16);
+        assert_eq!(longitudes(&gap), vec![-60.0, 60.0]);
+    }
+
+    /// Load points with properties into an index with adjusted options.
+    fn load_with(adjust: impl FnOnce(&mut Options), points: &[([f64; 2], JsonObject)]) -> Supercluster {
+        let mut options = setup().options;
+        adjust(&mut options);
+        let mut supercluster = Supercluster::new(options);
+        let points = points
+            .iter()
+            .map(|([lng, lat], properties)| Feature {
+                geometry: Some(Geometry::new(Point(vec![*lng, *lat]))),
+                properties: Some(properties.clone()),
+                ..Default::default()
+            })
+            .collect();
+
+        supercluster.load(points).unwrap();
+        supercluster
+    }
+
+    /// The number of clusters and points at each zoom level.
+    fn counts(supercluster: &Supercluster) -> Vec<usize> {
+        (0..=8).map(|zoom| supercluster.get_clusters([-180.0, -90.0, 180.0, 90.0], zoom).len()).collect()
+    }
+
+    #[test]
+    fn test_radius_schedule() {
+        let schedule = |options: &mut Options| options.radius_schedule = vec![(5, 100.0), (7, 10.0)];
+        let supercluster = load_with(schedule, &[]);
+
+        assert_eq!(supercluster.radius_at(0), 40.0);
+        assert_eq!(supercluster.radius_at(4), 40.0);
+        assert_eq!(supercluster.radius_at(5), 100.0);
+        assert_eq!(supercluster.radius_at(6), 100.0);
+        assert_eq!(supercluster.radius_at(7), 10.0);
+        assert_eq!(supercluster.radius_at(16), 10.0);
+
+        // Points about 1.4 pixels apart at zoom 0, twice as far at each following zoom
+        let points = [([0.0, 0.0], JsonObject::new()), ([1.0, 0.0], JsonObject::new())];
+        assert_eq!(counts(&load_with(|_| {}, &points)), vec![1, 1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(counts(&load_with(schedule, &points)), vec![1, 1, 1, 1, 1, 1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn test_radius_property() {
+        let radius = |options: &mut Options| options.radius_property = Some("radius".to_string());
+        let large = JsonObject::from_iter([("radius".to_string(), json!(150.0))]);
+
+        // The point with its own radius reaches the other one up to zoom 6, at 91 pixels
+        let points = [([0.0, 0.0], large.clone()), ([1.0, 0.0], JsonObject::new())];
+        let supercluster = load_with(radius, &points);
+        assert_eq!(counts(&supercluster), vec![1, 1, 1, 1, 1, 1, 1, 2, 2]);
+
+        // Clusters keep the largest radius of their points
+        let cluster = &supercluster.trees[0].data[..supercluster.stride];
+        assert_eq!(cluster[supercluster.offset_radius.unwrap()], 150.0);
+
+        // Points without one use the radius of the zoom level
+        let points = [([0.0, 0.0], JsonObject::new()), ([1.0, 0.0], JsonObject::new())];
+        assert_eq!(counts(&load_with(radius, &points)), vec![1, 1, 1, 1, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_radius_property_mixed_with_points_without_one() {
+        let radius = |options: &mut Options| options.radius_property = Some("radius".to_string());
+        let small = JsonObject::from_iter([("radius".to_string(), json!(1.0))]);
+        let (a, b, c) = (([0.1, 0.0], small), ([0.0, 0.0], JsonObject::new()), ([3.0, 0.0], JsonObject::new()));
+
+        // The first two points merge at zoom 8, their cluster reaches the third one up to zoom 3
+        let supercluster = load_with(radius, &[b.clone(), a.clone(), c.clone()]);
+        assert_eq!(counts(&supercluster), vec![1, 1, 1, 1, 2, 2, 2, 2, 2]);
+        let cluster = &supercluster.trees[4].data[..supercluster.stride];
+        assert_eq!(cluster[supercluster.offset_radius.unwrap()], 40.0);
+
+        let supercluster = load_with(radius, &[c, b, a]);
+        assert_eq!(counts(&supercluster)[..4], [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_get_tile_buffer_takes_point_radius() {
+        let small = |options: &mut Options| {
+            options.radius = 10.0;
+            options.radius_property = Some("radius".to_string());
+        };
+        let large = JsonObject::from_iter([("radius".to_string(), json!(50.0))]);
+        // A point in tile 1/0/0 and one about 28 pixels beyond its right edge
+        let inside = ([-90.0, 45.0], JsonObject::new());
+        let beyond = ([10.0, 10.0], JsonObject::new());
+        let elsewhere = ([100.0, -45.0], large);
+
+        let supercluster = load_with(small, &[inside.clone(), beyond.clone()]);
+        assert_eq!(supercluster.get_tile(1, 0.0, 0.0).unwrap().features.len(), 1);
+
+        let supercluster = load_with(small, &[inside, beyond, elsewhere]);
+        assert_eq!(supercluster.get_tile(1, 0.0, 0.0).unwrap().features.len(), 2);
+    }
+
+    #[test]
+    fn test_weighted_centroid() {
+        let weighted = |options: &mut Options| options.weight_property = Some("w".to_string());
+        let weight = |w: f64| JsonObject::from_iter([("w".to_string(), json!(w))]);
+        let supercluster = load_with(
+            weighted,
+            &[([0.0, 0.0], weight(3.0)), ([2.0, 0.0], weight(1.0)), ([10.0, 0.0], weight(4.0))],
+        );
+        let center = |cluster: &Feature| match &cluster.geometry.as_ref().unwrap().value {
+            Point(coords) => coords[0],
+            _ => panic!("expected a point"),
+        };
+
+        // The first two points are clustered at zoom 3, the third one is not
+        let clusters = supercluster.get_clusters([-180.0, -90.0, 180.0, 90.0], 3);
+        let cluster = clusters.iter().find(|cluster| cluster.contains_property("cluster")).unwrap();
+        assert_eq!(clusters.len(), 2);
+        assert!((center(cluster) - 0.5).abs() < 1e-9);
+        assert_eq!(cluster.property("weight_sum"), Some(&json!(4.0)));
+        assert_eq!(cluster.property("point_count"), Some(&json!(2)));
+
+        // Merged clusters are weighted by their weight rather than their number of points
+        let clusters = supercluster.get_clusters([-180.0, -90.0, 180.0, 90.0], 0);
+        assert_eq!(clusters.len(), 1);
+        assert!((center(&clusters[0]) - 5.25).abs() < 1e-9);
+        assert_eq!(clusters[0].property("weight_sum"), Some(&json!(8.0)));
+        assert_eq!(clusters[0].property("point_count"), Some(&json!(3)));
+    }
+
+    #[test]
+    fn test_stable_ids_ignore_input_order() {
+        // Points spread over a few clusters, in pairs at the same coordinates, the first pair identical
+        let mut points: Vec<Feature> = (0..60)
+            .map(|i| Feature {
+                geometry: Some(Geometry::new(Point(vec![(i % 6) as f64 * 0.7, (i % 5) as f64 * 0.4]))),
+                properties: Some(JsonObject::from_iter([("n".to_string(), json!(i % 3))])),
+                id: Some(geojson::feature::Id::Number((if i == 30 { 0 } else { i }).into())),
+                ..Default::default()
+            })
+            .collect();
+        let load = |points: Vec<Feature>| {
+            let mut supercluster = setup();
+            supercluster.options.stable_ids = true;
+            supercluster.load(points).unwrap();
+            supercluster
+        };
+
+        let supercluster = load(points.clone());
+        points.reverse();
+        let n = points.len();
+        let shuffled = load((0..n).map(|i| points[(i * 7) % n].clone()).collect());
+
+        for zoom in 0..=17 {
+            let clusters = supercluster.get_clusters([-180.0, -90.0, 180.0, 90.0], zoom);
+            assert_eq!(clusters, shuffled.get_clusters([-180.0, -90.0, 180.0, 90.0], zoom));
+
+            for cluster in clusters.iter().filter(|cluster| cluster.contains_property("cluster_id")) {
+                let cluster_id = cluster.property("cluster_id").unwrap().as_u64().unwrap() as usize;
+                assert_eq!(supercluster.get_children(cluster_id), shuffled.get_children(cluster_id));
+                assert_eq!(supercluster.get_leaves(cluster_id, usize::MAX, 0), shuffled.get_leaves(cluster_id, usize::MAX, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_cartesian() {
+        // A world of 1000 units with y pointing up, extended down from 500 to -500
+        let cartesian = |options: &mut Options| {
+            options.coordinate_system = CoordinateSystem::Cartesian { bounds: [0.0, 0.0, 1000.0, 500.0] }
+        };
+        let supercluster = load_with(
+            cartesian,
+            &[
+                ([100.0, 100.0], JsonObject::new()),
+                ([150.0, 100.0], JsonObject::new()),
+                ([900.0, 400.0], JsonObject::new()),
+                ([5.0, 450.0], JsonObject::new()),
+            ],
+        );
+        let coordinates = |feature: &Feature| match &feature.geometry.as_ref().unwrap().value {
+            Point(coords) => [coords[0], coords[1]],
+            _ => panic!("expected a point"),
+        };
+
+        // The first two points are 50 units, 25.6 pixels at zoom 0, apart
+        let clusters = supercluster.get_clusters([0.0, 0.0, 200.0, 200.0], 0);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(coordinates(&clusters[0]), [125.0, 100.0]);
+        assert_eq!(clusters[0].property("point_count"), Some(&json!(2)));
+        assert_eq!(supercluster.get_clusters([0.0, 0.0, 200.0, 200.0], 1).len(), 2);
+
+        // Bounding boxes are planar and do not wrap
+        assert_eq!(supercluster.get_clusters([800.0, 300.0, 1000.0, 500.0], 1).len(), 1);
+        assert_eq!(supercluster.get_clusters([-1000.0, -1000.0, 2000.0, 2000.0], 1).len(), 4);
+        assert!(supercluster.get_clusters([900.0, 0.0, 100.0, 500.0], 1).is_empty());
+
+        // Tiles are laid out from the top left corner of the bounds
+        let tile = supercluster.get_tile(1, 0.0, 0.0).unwrap();
+        let mut points: Vec<[f64; 2]> = tile.features.iter().map(coordinates).collect();
+        points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+        assert_eq!(points, vec![[5.0, 51.0], [102.0, 410.0], [154.0, 410.0]]);
+
+        // The point at the left edge does not wrap into the rightmost tile
+        let tile = supercluster.get_tile(1, 1.0, 0.0).unwrap();
+        assert_eq!(tile.features.iter().map(coordinates).collect::<Vec<_>>(), vec![[410.0, 102.0]]);
+        assert!(supercluster.get_tile(1, 0.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_points_without_properties() {
+        let mut supercluster = setup();
+        let points = [[0.0, 0.0], [100.0, 0.0]]
+            .iter()
+            .map(|&[lng, lat]| Feature {
+                geometry: Some(Geometry::new(Point(vec![lng, lat]))),
+                ..Default::default()
+            })
+            .collect();
+        supercluster.load(points).unwrap();
+
+        assert_eq!(supercluster.get_clusters([-180.0, -90.0, 180.0, 90.0], 0).len(), 2);
+        let tile = supercluster.get_tile(0, 0.0, 0.0).unwrap();
+        assert_eq!(tile.features.len(), 2);
+        assert!(tile.features.iter().all(|feature| feature.properties == Some(JsonObject::new())));
+    }
+
+    #[test]
+    fn test_empty_index() {
+        let unloaded = setup();
+        let empty = load_points(&[]);
+
+        for supercluster in [&unloaded, &empty] {
+            for zoom in 0..=17 {
+                assert!(supercluster.get_clusters([-180.0, -90.0, 180.0, 90.0], zoom).is_empty());
+                assert!(supercluster.get_tile(zoom, 0.0, 0.0).is_none());
+            }
+            assert!(supercluster.get_children(0).is_err());
+            assert!(supercluster.get_leaves(0, 10, 0).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_limit_zoom() {
+        let supercluster = setup();
+
+        assert_eq!(supercluster.limit_zoom(5), 5);
+    }
+
+    #[test]
+    fn test_get_origin_id() {
+        let supercluster = setup();
+
+        assert_eq!(supercluster.get_origin_id(100), 3);
+    }
+
+    #[test]
+    fn test_get_origin_zoom() {
+        let supercluster = setup();
+
+        assert_eq!(supercluster.get_origin_zoom(100), 4);
+    }
+
+    #[test]
+    fn test_get_cluster_json_with_cluster_props() {
+        let data = [0.0, 0.0, 0.0, 0.0, 0.0, 3.0, 0.0];
+        let i = 0;
+        let mut cluster_props = JsonObject::new();
+
+        cluster_props.insert("cluster".to_string(), json!(false));
+        cluster_props.insert("cluster_id".to_string(), json!(0));
+        cluster_props.insert("point_count".to_string(), json!(0));
+        cluster_props.insert("name".to_string(), json!("name".to_string()));
+        cluster_props.insert(
+            "point_count_abbreviated".to_string(),
+            json!("0".to_string()),
+        );
+
+        let result = get_cluster_json(
+            &data,
+            i,
+            &vec![cluster_props].into(),
+            None,
+            CoordinateSystem::Geographic,
+        );
+
+        assert_eq!(result.id, Some(Id::String("0".to_string())));
+
+        assert!(result.property("cluster").unwrap().as_bool().unwrap());
+        assert_eq!(result.property("cluster_id").unwrap().as_i64().unwrap(), 0);
+        assert_eq!(result.property("point_count").unwrap().as_i64().unwrap(), 3);
+        assert_eq!(
+            result.property("name").unwrap().as_str().unwrap(),
+            "name".to_string()
+        );
+        assert_eq!(
+            result
+                .property("point_count_abbreviated")
+                .unwrap()
+                .as_str()
+                .unwrap(),
+            "3".to_string()
+        );
+
+        let coordinates = match result.geometry {
+            Some(geometry) => match geometry.value {
+                Point(coords) => coords,
+                _ => vec![],
+            },
+            None => vec![],
+        };
+
+        assert_eq!(coordinates, vec![-180.0, 85.05112877980659]);
+    }
+
+    #[test]
+    fn test_get_cluster_json_without_cluster_props() {
+        let data = [0.0, 0.0, 0.0, 0.0, 0.0, 3.0, 0.0];
+        let i = 0;
+        let cluster_props = vec![].into();
+
+        let result = get_cluster_json(&data, i, &cluster_props, None, CoordinateSystem::Geographic);
+
+        assert_eq!(result.id, Some(Id::String("0".to_string())));
+
+        assert!(result
+            .property("cluster")
+            .as_ref()
+            .unwrap()
+            .as_bool()
+            .unwrap());
+        assert_eq!(result.property("cluster_id").unwrap().as_i64().unwrap(), 0);
+        assert_eq!(result.property("point_count").unwrap().as_i64().unwrap(), 3);
+        assert!(result.property("name").is_none());
+        assert_eq!(
+            result
+                .property("point_count_abbreviated")
+                .unwrap()
+                .as_str()
+                .unwrap(),
+            "3".to_string()
+        );
+
+        let coordinates = match result.geometry {
+            Some(geometry) => match geometry.value {
+                Point(coords) => coords,
+                _ => vec![],
+            },
+            None => vec![],
+        };
+
+        assert_eq!(coordinates, vec![-180.0, 85.05112877980659]);
+    }
+
+    #[test]
+    fn test_get_cluster_properties_with_cluster_props() {
+        let data = [0.0, 0.0, 0.0, 0.0, 0.0, 10000.0, 0.0];
+        let i = 0;
+        let mut cluster_props = JsonObject::new();
+
+        cluster_props.insert("cluster".to_string(), json!(false));
+        cluster_props.insert("cluster_id".to_string(), json!(0));
+        cluster_props.insert("point_count".to_string(), json!(0));
+        cluster_props.insert("name".to_string(), json!("name".to_string()));
+        cluster_props.insert(
+            "point_count_abbreviated".to_string(),
+            json!("0".to_string()),
+        );
+
+        let result = get_cluster_properties(&data, i, &vec![cluster_props].into(), None);
+
+        assert!(result.get("cluster").unwrap().as_bool().unwrap());
+        assert_eq!(result.get("cluster_id").unwrap().as_i64().unwrap(), 0);
+        assert_eq!(result.get("point_count").unwrap().as_i64().unwrap(), 10000);
+        assert_eq!(
+            result.get("name").unwrap().as_str().unwrap(),
+            "name".to_string()
+        );
+        assert_eq!(
+            result
+                .get("point_count_abbreviated")
+                .unwrap()
+                .as_str()
+                .unwrap(),
+            "10k".to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_cluster_properties_without_cluster_props() {
+        let data = [0.0, 0.0, 0.0, 0.0, 0.0, 1000.0, 0.0];
+        let i = 0;
+        let cluster_props = vec![].into();
+
+        let result = get_cluster_properties(&data, i, &cluster_props, None);
+
+        assert!(result.get("cluster").unwrap().as_bool().unwrap());
+        assert_eq!(result.get("cluster_id").unwrap().as_i64().unwrap(), 0);
+        assert_eq!(result.get("point_count").unwrap().as_i64().unwrap(), 1000);
+        assert!(result.get("name").is_none());
+        assert_eq!(
+            result
+                .get("point_count_abbreviated")
+                .unwrap()
+                .as_str()
+                .unwrap(),
+            "1k".to_string()
+        );
+    }
+
+    #[test]
+    fn test_lng_x() {
+        assert_eq!(lng_x(0.0), 0.5);
+        assert_eq!(lng_x(180.0), 1.0);
+        assert_eq!(lng_x(-180.0), 0.0);
+        assert_eq!(lng_x(90.0), 0.75);
+        assert_eq!(lng_x(-90.0), 0.25);
+    }
+
+    #[test]
+    fn test_lat_y() {
+        assert_eq!(lat_y(0.0), 0.5);
+        assert_eq!(lat_y(90.0), 0.0);
+        assert_eq!(lat_y(-90.0), 1.0);
+        assert_eq!(lat_y(45.0), 0.35972503691520497);
+        assert_eq!(lat_y(-45.0), 0.640274963084795);
+    }
+
+    #[test]
+    fn test_x_lng() {
+        assert_eq!(x_lng(0.5), 0.0);
+        assert_eq!(x_lng(1.0), 180.0);
+        assert_eq!(x_lng(0.0), -180.0);
+        assert_eq!(x_lng(0.75), 90.0);
+        assert_eq!(x_lng(0.25), -90.0);
+    }
+
+    #[test]
+    fn test_y_lat() {
+        assert_eq!(y_lat(0.5), 0.0);
+        assert_eq!(y_lat(0.875), -79.17133464081944);
+        assert_eq!(y_lat(0.125), 79.17133464081945);
+    }
+}
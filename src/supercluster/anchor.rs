@@ -0,0 +1,326 @@
+//! Points standing in for non-point geometries, which are clustered at that point while keeping
+//! their geometry.
+
+use geojson::{Position, Value};
+
+/// How the point a non-point geometry is clustered at is chosen. Both only look at the parts of
+/// the highest dimension with a size, e.g. at the polygons of a collection with polygons and lines,
+/// and compute in plain coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Anchor {
+    /// The center of mass, which may lie outside of the geometry, e.g. for a U-shaped polygon.
+    Centroid,
+
+    /// A point on the geometry: for polygons the middle of the widest horizontal section through
+    /// the middle of their extent, otherwise the vertex closest to the centroid.
+    Representative,
+}
+
+impl Anchor {
+    /// Compute the point a geometry is clustered at.
+    ///
+    /// # Arguments
+    ///
+    /// - `geometry`: The geometry.
+    ///
+    /// # Returns
+    ///
+    /// The point, or `None` if the geometry has no positions or a position without two finite
+    /// coordinates.
+    pub fn point(self, geometry: &Value) -> Option<[f64; 2]> {
+        let mut parts = Parts::default();
+        parts.add(geometry);
+        if !parts.valid || parts.vertices.is_empty() {
+            return None;
+        }
+
+        let centroid = parts.centroid();
+        match self {
+            Anchor::Centroid => Some(centroid),
+            Anchor::Representative => Some(parts.representative(centroid)),
+        }
+    }
+}
+
+/// The positions of a geometry, grouped by the dimension of the part they belong to.
+struct Parts<'a> {
+    /// Every position, of points, lines and rings.
+    vertices: Vec<[f64; 2]>,
+
+    /// Lines, as their positions.
+    lines: Vec<&'a [Position]>,
+
+    /// Polygons as their rings, the exterior first.
+    polygons: Vec<&'a [Vec<Position>]>,
+
+    /// Whether every position has two finite coordinates.
+    valid: bool,
+}
+
+impl Default for Parts<'_> {
+    fn default() -> Self {
+        Parts {
+            vertices: Vec::new(),
+            lines: Vec::new(),
+            polygons: Vec::new(),
+            valid: true,
+        }
+    }
+}
+
+impl<'a> Parts<'a> {
+    fn add(&mut self, geometry: &'a Value) {
+        match geometry {
+            Value::Point(position) => self.add_positions(std::slice::from_ref(position)),
+            Value::MultiPoint(positions) => self.add_positions(positions),
+            Value::LineString(line) => self.add_line(line),
+            Value::MultiLineString(lines) => lines.iter().for_each(|line| self.add_line(line)),
+            Value::Polygon(rings) => self.add_polygon(rings),
+            Value::MultiPolygon(polygons) => polygons.iter().for_each(|rings| self.add_polygon(rings)),
+            Value::GeometryCollection(geometries) => {
+                geometries.iter().for_each(|geometry| self.add(&geometry.value))
+            }
+        }
+    }
+
+    fn add_positions(&mut self, positions: &[Position]) {
+        for position in positions {
+            match position.as_slice() {
+                [x, y, ..] if x.is_finite() && y.is_finite() => self.vertices.push([*x, *y]),
+                _ => self.valid = false,
+            }
+        }
+    }
+
+    fn add_line(&mut self, line: &'a [Position]) {
+        self.add_positions(line);
+        self.lines.push(line);
+    }
+
+    fn add_polygon(&mut self, rings: &'a [Vec<Position>]) {
+        rings.iter().for_each(|ring| self.add_positions(ring));
+        self.polygons.push(rings);
+    }
+
+    fn is_polygonal(&self) -> bool {
+        self.polygons.iter().any(|rings| rings.first().is_some_and(|ring| ring_area(ring).0 != 0.0))
+    }
+
+    /// The lines measured when there is no area: lines and polygon rings.
+    fn linework(&self) -> impl Iterator<Item = &[Position]> {
+        let rings = self.polygons.iter().flat_map(|rings| rings.iter().map(|ring| ring.as_slice()));
+        self.lines.iter().copied().chain(rings)
+    }
+
+    fn is_lineal(&self) -> bool {
+        self.linework().any(|line| line_length(line).0 > 0.0)
+    }
+
+    fn centroid(&self) -> [f64; 2] {
+        if self.is_polygonal() {
+            let (mut area, mut cx, mut cy) = (0.0, 0.0, 0.0);
+            for rings in &self.polygons {
+                for (i, ring) in rings.iter().enumerate() {
+                    let (ring_area, [x, y]) = ring_area(ring);
+                    // Holes are subtracted whatever their winding order
+                    let weight = if i == 0 { ring_area.abs() } else { -ring_area.abs() };
+                    area += weight;
+                    cx += weight * x;
+                    cy += weight * y;
+                }
+            }
+            if area != 0.0 {
+                return [cx / area, cy / area];
+            }
+        }
+
+        if self.is_lineal() {
+            let (mut length, mut cx, mut cy) = (0.0, 0.0, 0.0);
+            for line in self.linework() {
+                let (line_length, [x, y]) = line_length(line);
+                length += line_length;
+                cx += line_length * x;
+                cy += line_length * y;
+            }
+            return [cx / length, cy / length];
+        }
+
+        let n = self.vertices.len() as f64;
+        let (sx, sy) = self.vertices.iter().fold((0.0, 0.0), |(sx, sy), [x, y]| (sx + x, sy + y));
+        [sx / n, sy / n]
+    }
+
+    fn representative(&self, centroid: [f64; 2]) -> [f64; 2] {
+        if self.is_polygonal() {
+            let widest = self
+                .polygons
+                .iter()
+                .filter_map(|rings| widest_section(rings))
+                .max_by(|a, b| a.0.total_cmp(&b.0));
+            if let Some((_, point)) = widest {
+                return point;
+            }
+        }
+
+        let distance = |[x, y]: [f64; 2]| (x - centroid[0]).powi(2) + (y - centroid[1]).powi(2);
+        let closest = |vertices: &mut dyn Iterator<Item = [f64; 2]>| {
+            vertices.min_by(|&a, &b| distance(a).total_cmp(&distance(b)))
+        };
+        let line_vertex = if self.is_lineal() {
+            closest(&mut self.linework().flatten().map(|p| [p[0], p[1]]))
+        } else {
+            None
+        };
+        line_vertex
+            .or_else(|| closest(&mut self.vertices.iter().copied()))
+            .unwrap_or(centroid)
+    }
+}
+
+/// The signed area of a ring and its centroid, which is meaningless when the area is 0.
+fn ring_area(ring: &[Position]) -> (f64, [f64; 2]) {
+    let (mut area, mut cx, mut cy) = (0.0, 0.0, 0.0);
+    for (a, b) in edges(ring) {
+        let cross = a[0] * b[1] - b[0] * a[1];
+        area += cross;
+        cx += (a[0] + b[0]) * cross;
+        cy += (a[1] + b[1]) * cross;
+    }
+    if area == 0.0 {
+        return (0.0, [0.0, 0.0]);
+    }
+    (area / 2.0, [cx / (3.0 * area), cy / (3.0 * area)])
+}
+
+/// The edges of a ring, closing it if it is not.
+fn edges(ring: &[Position]) -> impl Iterator<Item = (&Position, &Position)> {
+    ring.iter().zip(ring.iter().cycle().skip(1)).take(ring.len())
+}
+
+/// The length of a line and its centroid, which is meaningless when the length is 0.
+fn line_length(line: &[Position]) -> (f64, [f64; 2]) {
+    let (mut length, mut cx, mut cy) = (0.0, 0.0, 0.0);
+    for pair in line.windows(2) {
+        let (a, b) = (&pair[0], &pair[1]);
+        let segment = (b[0] - a[0]).hypot(b[1] - a[1]);
+        length += segment;
+        cx += segment * (a[0] + b[0]) / 2.0;
+        cy += segment * (a[1] + b[1]) / 2.0;
+    }
+    if length == 0.0 {
+        return (0.0, [0.0, 0.0]);
+    }
+    (length, [cx / length, cy / length])
+}
+
+/// The width and middle of the widest section of a polygon along a horizontal line through the
+/// middle of its extent, moved between vertices so that it crosses edges rather than touching them.
+fn widest_section(rings: &[Vec<Position>]) -> Option<(f64, [f64; 2])> {
+    let exterior = rings.first()?;
+    let min_y = exterior.iter().map(|p| p[1]).fold(f64::INFINITY, f64::min);
+    let max_y = exterior.iter().map(|p| p[1]).fold(f64::NEG_INFINITY, f64::max);
+    let middle = (min_y + max_y) / 2.0;
+
+    let (mut below, mut above) = (min_y, max_y);
+    for position in rings.iter().flatten() {
+        let y = position[1];
+        if y <= middle && y > below {
+            below = y;
+        } else if y > middle && y < above {
+            above = y;
+        }
+    }
+    let scan_y = (below + above) / 2.0;
+
+    let mut crossings = Vec::new();
+    for (a, b) in rings.iter().flat_map(|ring| edges(ring)) {
+        if (a[1] > scan_y) != (b[1] > scan_y) {
+            crossings.push(a[0] + (scan_y - a[1]) * (b[0] - a[0]) / (b[1] - a[1]));
+        }
+    }
+    crossings.sort_by(f64::total_cmp);
+
+    crossings
+        .chunks_exact(2)
+        .map(|section| (section[1] - section[0], [(section[0] + section[1]) / 2.0, scan_y]))
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polygon(rings: &[&[[f64; 2]]]) -> Value {
+        Value::Polygon(rings.iter().map(|ring| ring.iter().map(|p| p.to_vec()).collect()).collect())
+    }
+
+    #[test]
+    fn test_u_shaped_polygon() {
+        let u = polygon(&[&[
+            [0.0, 0.0],
+            [3.0, 0.0],
+            [3.0, 3.0],
+            [2.0, 3.0],
+            [2.0, 1.0],
+            [1.0, 1.0],
+            [1.0, 3.0],
+            [0.0, 3.0],
+            [0.0, 0.0],
+        ]]);
+
+        let [x, y] = Anchor::Centroid.point(&u).unwrap();
+        assert_eq!(x, 1.5);
+        assert!((y - 19.0 / 14.0).abs() < 1e-12);
+
+        // The centroid is in the notch, the representative point in an arm
+        assert_eq!(Anchor::Representative.point(&u), Some([2.5, 2.0]));
+    }
+
+    #[test]
+    fn test_polygon_with_hole() {
+        let holed = polygon(&[
+            &[[10.0, 10.0], [14.0, 10.0], [14.0, 14.0], [10.0, 14.0], [10.0, 10.0]],
+            &[[11.0, 11.0], [11.0, 13.0], [13.0, 13.0], [13.0, 11.0], [11.0, 11.0]],
+        ]);
+
+        assert_eq!(Anchor::Centroid.point(&holed), Some([12.0, 12.0]));
+        assert_eq!(Anchor::Representative.point(&holed), Some([13.5, 12.0]));
+    }
+
+    #[test]
+    fn test_unclosed_ring() {
+        let square = polygon(&[&[[0.0, 0.0], [2.0, 0.0], [2.0, 2.0], [0.0, 2.0]]]);
+
+        assert_eq!(Anchor::Centroid.point(&square), Some([1.0, 1.0]));
+    }
+
+    #[test]
+    fn test_line_and_points() {
+        let line = Value::LineString(vec![vec![20.0, 0.0], vec![22.0, 0.0], vec![22.0, 2.0]]);
+
+        assert_eq!(Anchor::Centroid.point(&line), Some([21.5, 0.5]));
+        assert_eq!(Anchor::Representative.point(&line), Some([22.0, 0.0]));
+
+        let points = Value::MultiPoint(vec![vec![30.0, 0.0], vec![32.0, 0.0], vec![31.0, 3.0]]);
+
+        assert_eq!(Anchor::Centroid.point(&points), Some([31.0, 1.0]));
+        assert_eq!(Anchor::Representative.point(&points), Some([30.0, 0.0]));
+    }
+
+    #[test]
+    fn test_collection_uses_highest_dimension() {
+        let collection = Value::GeometryCollection(vec![
+            geojson::Geometry::new(Value::LineString(vec![vec![20.0, 0.0], vec![22.0, 0.0]])),
+            geojson::Geometry::new(polygon(&[&[[0.0, 0.0], [2.0, 0.0], [2.0, 2.0], [0.0, 2.0], [0.0, 0.0]]])),
+        ]);
+
+        assert_eq!(Anchor::Centroid.point(&collection), Some([1.0, 1.0]));
+    }
+
+    #[test]
+    fn test_invalid_geometries() {
+        assert_eq!(Anchor::Centroid.point(&Value::LineString(vec![])), None);
+        assert_eq!(Anchor::Centroid.point(&Value::LineString(vec![vec![0.0, f64::NAN]])), None);
+        assert_eq!(Anchor::Representative.point(&Value::MultiPoint(vec![vec![1.0]])), None);
+    }
+}
@@ -0,0 +1,835 @@
+//! Versioned binary format of a built index, holding the options, the input points, the cluster
+//! properties and the trees of every zoom level, so that an index can be reloaded without
+//! clustering again.
+//!
+//! All numbers are little-endian and every array starts at a multiple of 8 bytes:
+//!
+//! - `MAGIC`, then the `u32` format version and 4 bytes of padding.
+//! - Metadata: a JSON object with the options, see `Supercluster::to_bytes`.
+//! - Points: a table of record offsets followed by the records, see `write_point`.
+//! - Cluster properties: a table of offsets followed by one JSON object per cluster.
+//! - Trees: per zoom level the node size and the `u64` ids, `f64` coordinates and `f64` data.
+
+use super::store::{Bytes, Column, Record, Records};
+use super::{Anchor, CoordinateSystem, KDBush, Options, Reducer, Supercluster, MAX_ZOOM};
+use super::{OFFSET_ID, OFFSET_NUM, OFFSET_PARENT, OFFSET_PROP};
+use geojson::{feature::Id, Feature, Geometry, JsonObject, Value::Point};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Leading bytes of every serialized index.
+pub const MAGIC: &[u8; 8] = b"PYSCLSTR";
+
+/// Version of the format written by `Supercluster::to_bytes`.
+pub const VERSION: u32 = 1;
+
+impl Supercluster {
+    /// Serialize the index.
+    ///
+    /// # Arguments
+    ///
+    /// - `extra`: Metadata of the caller, such as how to recreate the reducer, stored under `"extra"`.
+    ///
+    /// # Returns
+    ///
+    /// The serialized index, to be read back with `Supercluster::from_bytes` or `Supercluster::from_mapped`.
+    pub fn to_bytes(&self, extra: &JsonObject) -> Vec<u8> {
+        let mut out = Writer::default();
+        out.bytes(MAGIC);
+        out.u32(VERSION);
+        out.align();
+
+        let meta = json!({
+            "min_zoom": self.options.min_zoom,
+            "max_zoom": self.options.max_zoom,
+            "min_points": self.options.min_points,
+            "radius": self.options.radius,
+            "radius_schedule": self.options.radius_schedule,
+            "radius_property": self.options.radius_property,
+            "extent": self.options.extent,
+            "node_size": self.options.node_size,
+            "weight_property": self.options.weight_property,
+            "reducer": self.options.reducer.is_some(),
+            "stable_ids": self.options.stable_ids,
+            "bounds": match self.options.coordinate_system {
+                CoordinateSystem::Geographic => None,
+                CoordinateSystem::Cartesian { bounds } => Some(bounds),
+            },
+            "geometry_anchor": match self.options.geometry_anchor {
+                None => None,
+                Some(Anchor::Centroid) => Some("centroid"),
+                Some(Anchor::Representative) => Some("representative"),
+            },
+            "extra": extra,
+        });
+        out.block(meta.to_string().as_bytes());
+
+        out.records(&self.points);
+        out.records(&self.cluster_props);
+
+        out.u64(self.trees.len() as u64);
+        for tree in &self.trees {
+            out.u64(tree.node_size as u64);
+            out.u64(tree.ids.len() as u64);
+            for &id in tree.ids.iter() {
+                out.u64(id as u64);
+            }
+            out.f64s(&tree.coords);
+            out.f64s(&tree.data);
+        }
+
+        out.buf
+    }
+
+    /// Read the metadata of a serialized index, so that its reducer can be recreated before the index is.
+    ///
+    /// # Arguments
+    ///
+    /// - `bytes`: The serialized index.
+    ///
+    /// # Returns
+    ///
+    /// The metadata object, with the caller metadata under `"extra"`, or a description of why the bytes
+    /// are not a serialized index.
+    pub fn read_meta(bytes: &[u8]) -> Result<JsonObject, String> {
+        let mut input = Reader { bytes, pos: 0 };
+        input.header()
+    }
+
+    /// Deserialize an index written by `Supercluster::to_bytes`, copying everything out of `bytes`.
+    ///
+    /// # Arguments
+    ///
+    /// - `bytes`: The serialized index.
+    /// - `reducer`: The reducer to use for future loads, required if the index was built with one.
+    ///
+    /// # Returns
+    ///
+    /// The index, or a description of why the bytes are not a serialized index.
+    pub fn from_bytes(bytes: &[u8], reducer: Option<Arc<dyn Reducer>>) -> Result<Self, String> {
+        let meta = Self::read_meta(bytes)?;
+        let mut index = Supercluster::new(options_from_meta(&meta, reducer)?);
+        let layout = Layout::read(bytes, index.stride)?;
+
+        index.points = layout.points.decode(bytes)?.into();
+        index.cluster_props = layout.cluster_props.decode(bytes)?.into();
+        for (tree, at) in index.trees.iter_mut().zip(&layout.trees) {
+            let words = |(start, len): (usize, usize)| {
+                bytes[start..start + 8 * len]
+                    .chunks_exact(8)
+                    .map(|chunk| <[u8; 8]>::try_from(chunk).unwrap())
+            };
+            *tree = KDBush {
+                node_size: at.node_size,
+                ids: words(at.ids).map(|w| u64::from_le_bytes(w) as usize).collect::<Vec<_>>().into(),
+                coords: words(at.coords).map(f64::from_le_bytes).collect::<Vec<_>>().into(),
+                points: vec![],
+                data: words(at.data).map(f64::from_le_bytes).collect::<Vec<_>>().into(),
+            };
+        }
+        index.max_point_radius = index.compute_max_point_radius();
+
+        Ok(index)
+    }
+
+    /// Open an index written by `Supercluster::to_bytes` in place: trees are read from `bytes`
+    /// directly and points and cluster properties are decoded as they are accessed, so processes
+    /// mapping the same file share its memory. Every record is decoded once to check it, without
+    /// keeping it.
+    ///
+    /// # Arguments
+    ///
+    /// - `bytes`: The serialized index, starting at an address aligned to 8 bytes.
+    /// - `reducer`: The reducer to use for future loads, required if the index was built with one.
+    ///
+    /// # Returns
+    ///
+    /// The index, or a description of why the bytes are not a serialized index or cannot be used in place.
+    pub fn from_mapped(bytes: Bytes, reducer: Option<Arc<dyn Reducer>>) -> Result<Self, String> {
+        let data = (*bytes).as_ref();
+        if cfg!(target_endian = "big")
+            || std::mem::size_of::<usize>() != 8
+            || !(data.as_ptr() as usize).is_multiple_of(8)
+        {
+            return Err("Indexes can only be used in place on little-endian 64-bit platforms".to_string());
+        }
+
+        let meta = Self::read_meta(data)?;
+        let mut index = Supercluster::new(options_from_meta(&meta, reducer)?);
+        let layout = Layout::read(data, index.stride)?;
+        layout.points.check::<Feature>(data)?;
+        layout.cluster_props.check::<JsonObject>(data)?;
+
+        index.points = layout.points.mapped(&bytes);
+        index.cluster_props = layout.cluster_props.mapped(&bytes);
+        for (tree, at) in index.trees.iter_mut().zip(&layout.trees) {
+            *tree = KDBush {
+                node_size: at.node_size,
+                ids: mapped_column(&bytes, at.ids),
+                coords: mapped_column(&bytes, at.coords),
+                points: vec![],
+                data: mapped_column(&bytes, at.data),
+            };
+        }
+        index.max_point_radius = index.compute_max_point_radius();
+
+        Ok(index)
+    }
+}
+
+fn mapped_column<T>(bytes: &Bytes, (start, len): (usize, usize)) -> Column<T> {
+    Column::Mapped {
+        bytes: bytes.clone(),
+        start,
+        len,
+    }
+}
+
+/// Where the parts of a serialized index are, checked to be consistent.
+struct Layout {
+    points: RecordsAt,
+    cluster_props: RecordsAt,
+    trees: Vec<TreeAt>,
+}
+
+/// Position of a table of record offsets and of the records it delimits.
+struct RecordsAt {
+    table: usize,
+    count: usize,
+    body: usize,
+}
+
+/// Node size and the `(byte offset, length)` of the ids, coordinates and data of a tree.
+struct TreeAt {
+    node_size: usize,
+    ids: (usize, usize),
+    coords: (usize, usize),
+    data: (usize, usize),
+}
+
+impl Layout {
+    /// Locate the parts of a serialized index whose data arrays have the given stride, and whose
+    /// metadata has been checked to be readable.
+    fn read(bytes: &[u8], stride: usize) -> Result<Self, String> {
+        let mut input = Reader { bytes, pos: 0 };
+        let meta = input.header()?;
+        let points = input.records()?;
+        let cluster_props = input.records()?;
+
+        let num_trees = input.u64()?;
+        let max_zoom = meta.get("max_zoom").and_then(|v| v.as_u64()).ok_or_else(corrupt)?;
+        if num_trees != max_zoom + 2 {
+            return Err(corrupt());
+        }
+
+        let mut trees = Vec::new();
+        for _ in 0..num_trees {
+            let node_size = input.u64()? as usize;
+            let ids = input.words()?;
+            let coords = input.words()?;
+            let data = input.words()?;
+            let len = ids.1;
+            let id_out_of_range = bytes[ids.0..ids.0 + 8 * len]
+                .chunks_exact(8)
+                .any(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()) >= len as u64);
+            if id_out_of_range || coords.1 != 2 * len || data.1 != stride * len {
+                return Err(corrupt());
+            }
+            trees.push(TreeAt {
+                node_size,
+                ids,
+                coords,
+                data,
+            });
+        }
+
+        let layout = Layout {
+            points,
+            cluster_props,
+            trees,
+        };
+        layout.check_references(bytes, stride, &meta)?;
+        Ok(layout)
+    }
+
+    /// Check that the ids, parents and property indexes in the data arrays refer to points,
+    /// clusters and cluster properties that exist, since queries index with them unchecked.
+    fn check_references(&self, bytes: &[u8], stride: usize, meta: &JsonObject) -> Result<(), String> {
+        let zoom = |key: &str| meta.get(key).and_then(|v| v.as_u64()).ok_or_else(corrupt);
+        let (min_zoom, max_zoom) = (zoom("min_zoom")? as usize, zoom("max_zoom")? as usize);
+        let has_props = meta.get("reducer").and_then(|v| v.as_bool()) == Some(true);
+        let num_points = self.points.count;
+
+        let is_index = |value: f64, len: usize| value.fract() == 0.0 && value >= 0.0 && value < len as f64;
+        // Cluster ids encode the tree of their origin and its position in that tree
+        let is_cluster_id = |value: f64| {
+            if !(value.fract() == 0.0 && value >= num_points as f64 && value < 2f64.powi(53)) {
+                return false;
+            }
+            let offset = value as usize - num_points;
+            let origin_zoom = offset % 32;
+            origin_zoom > min_zoom && origin_zoom <= max_zoom + 1 && offset >> 5 < self.trees[origin_zoom].ids.1
+        };
+
+        for tree in &self.trees {
+            let (start, len) = tree.data;
+            for record in bytes[start..start + 8 * len].chunks_exact(8 * stride) {
+                let value = |offset: usize| {
+                    f64::from_le_bytes(record[8 * offset..8 * offset + 8].try_into().unwrap())
+                };
+                let num = value(OFFSET_NUM);
+                let valid = if num > 1.0 {
+                    is_cluster_id(value(OFFSET_ID))
+                        && (!has_props || is_index(value(OFFSET_PROP), self.cluster_props.count))
+                } else {
+                    num == 1.0 && is_index(value(OFFSET_ID), num_points)
+                };
+                let parent = value(OFFSET_PARENT);
+                if !valid || !(parent == -1.0 || is_cluster_id(parent)) {
+                    return Err(corrupt());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl RecordsAt {
+    fn offset(&self, bytes: &[u8], i: usize) -> usize {
+        let at = self.table + 8 * i;
+        self.body + u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize
+    }
+
+    fn decode<T: Record>(&self, bytes: &[u8]) -> Result<Vec<T>, String> {
+        (0..self.count)
+            .map(|i| T::decode(&bytes[self.offset(bytes, i)..self.offset(bytes, i + 1)]))
+            .collect()
+    }
+
+    /// Check that every record decodes, so that stored records can be decoded as they are accessed.
+    fn check<T: Record>(&self, bytes: &[u8]) -> Result<(), String> {
+        (0..self.count).try_for_each(|i| {
+            T::decode(&bytes[self.offset(bytes, i)..self.offset(bytes, i + 1)]).map(drop)
+        })
+    }
+
+    fn mapped<T>(&self, bytes: &Bytes) -> Records<T> {
+        Records::Mapped {
+            bytes: bytes.clone(),
+            table: self.table,
+            count: self.count,
+            body: self.body,
+        }
+    }
+}
+
+fn corrupt() -> String {
+    "Corrupt or truncated index data".to_string()
+}
+
+/// Recreate the options an index was built with.
+fn options_from_meta(meta: &JsonObject, reducer: Option<Arc<dyn Reducer>>) -> Result<Options, String> {
+    let number = |key: &str| meta.get(key).and_then(|v| v.as_f64()).ok_or_else(corrupt);
+    let options = Options {
+        min_zoom: number("min_zoom")? as u8,
+        max_zoom: number("max_zoom")? as u8,
+        min_points: number("min_points")? as u8,
+        radius: number("radius")?,
+        extent: number("extent")?,
+        node_size: number("node_size")? as usize,
+        reducer,
+        weight_property: meta
+            .get("weight_property")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string()),
+        stable_ids: meta.get("stable_ids").and_then(|v| v.as_bool()).unwrap_or(false),
+        radius_schedule: match meta.get("radius_schedule") {
+            Some(schedule) => serde_json::from_value(schedule.clone()).map_err(|_| corrupt())?,
+            None => vec![],
+        },
+        radius_property: meta
+            .get("radius_property")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string()),
+        coordinate_system: match meta.get("bounds") {
+            Some(serde_json::Value::Null) | None => CoordinateSystem::Geographic,
+            Some(bounds) => CoordinateSystem::Cartesian {
+                bounds: serde_json::from_value(bounds.clone()).map_err(|_| corrupt())?,
+            },
+        },
+        geometry_anchor: match meta.get("geometry_anchor").and_then(|v| v.as_str()) {
+            None => None,
+            Some("centroid") => Some(Anchor::Centroid),
+            Some("representative") => Some(Anchor::Representative),
+            Some(_) => return Err(corrupt()),
+        },
+    };
+    if number("max_zoom")? > MAX_ZOOM as f64 {
+        return Err(corrupt());
+    }
+    if meta.get("reducer").and_then(|v| v.as_bool()) != Some(options.reducer.is_some()) {
+        return Err(if options.reducer.is_some() {
+            "The index was built without a reducer".to_string()
+        } else {
+            "The index was built with a reducer, which has to be given again".to_string()
+        });
+    }
+    Ok(options)
+}
+
+/// Points are written as their geometry, id and properties: the coordinates of points or other
+/// geometries as GeoJSON, followed by a tagged id and the properties as a JSON object.
+impl Record for Feature {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Writer::default();
+        write_point(&mut out, self);
+        out.buf
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        read_point(bytes)
+    }
+}
+
+impl Record for JsonObject {
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(bytes).map_err(|_| corrupt())
+    }
+}
+
+fn write_point(out: &mut Writer, point: &Feature) {
+    match point.geometry.as_ref().map(|g| &g.value) {
+        None => out.u8(0),
+        Some(Point(coords)) => {
+            out.u8(1);
+            out.u64(coords.len() as u64);
+            for &c in coords {
+                out.f64(c);
+            }
+        }
+        Some(_) => {
+            out.u8(2);
+            let geometry = point.geometry.as_ref().map(|g| g.to_string()).unwrap_or_default();
+            out.block(geometry.as_bytes());
+        }
+    }
+    match &point.id {
+        None => out.u8(0),
+        Some(Id::String(s)) => {
+            out.u8(1);
+            out.block(s.as_bytes());
+        }
+        Some(Id::Number(n)) => {
+            out.u8(2);
+            out.block(n.to_string().as_bytes());
+        }
+    }
+    let properties = point.properties.as_ref().map(|p| serde_json::to_vec(p).unwrap_or_default());
+    match properties {
+        None => out.u8(0),
+        Some(properties) => {
+            out.u8(1);
+            out.block(&properties);
+        }
+    }
+}
+
+fn read_point(record: &[u8]) -> Result<Feature, String> {
+    let mut input = Reader { bytes: record, pos: 0 };
+    let geometry = match input.u8()? {
+        0 => None,
+        1 => {
+            let len = input.len()?;
+            let coords = (0..len).map(|_| input.f64()).collect::<Result<Vec<_>, _>>()?;
+            Some(Geometry::new(Point(coords)))
+        }
+        2 => Some(std::str::from_utf8(input.block()?)
+            .ok()
+            .and_then(|s| s.parse::<Geometry>().ok())
+            .ok_or_else(corrupt)?),
+        _ => return Err(corrupt()),
+    };
+    let id = match input.u8()? {
+        0 => None,
+        1 => Some(Id::String(
+            String::from_utf8(input.block()?.to_vec()).map_err(|_| corrupt())?,
+        )),
+        2 => Some(Id::Number(serde_json::from_slice(input.block()?).map_err(|_| corrupt())?)),
+        _ => return Err(corrupt()),
+    };
+    let properties = match input.u8()? {
+        0 => None,
+        1 => Some(serde_json::from_slice(input.block()?).map_err(|_| corrupt())?),
+        _ => return Err(corrupt()),
+    };
+    Ok(Feature {
+        bbox: None,
+        geometry,
+        id,
+        properties,
+        foreign_members: None,
+    })
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// Pad to a multiple of 8 bytes.
+    fn align(&mut self) {
+        self.buf.resize(self.buf.len().next_multiple_of(8), 0);
+    }
+
+    /// Length-prefixed bytes.
+    fn block(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.bytes(bytes);
+        self.align();
+    }
+
+    /// Length-prefixed numbers.
+    fn f64s(&mut self, values: &[f64]) {
+        self.u64(values.len() as u64);
+        for &value in values {
+            self.f64(value);
+        }
+    }
+
+    /// A count, the offsets of the records relative to the end of the offsets table and the offset
+    /// just past the last record, then the records themselves.
+    fn records<T: Record>(&mut self, records: &Records<T>) {
+        let count = records.len();
+        self.u64(count as u64);
+        let table = self.buf.len();
+        self.buf.resize(table + 8 * (count + 1), 0);
+        let start = self.buf.len();
+        for i in 0..count {
+            self.set_offset(table + 8 * i, start);
+            let record = records.get(i).encode();
+            self.bytes(&record);
+        }
+        self.set_offset(table + 8 * count, start);
+        self.align();
+    }
+
+    /// Store the current length relative to `start` at `at`.
+    fn set_offset(&mut self, at: usize, start: usize) {
+        let offset = (self.buf.len() - start) as u64;
+        self.buf[at..at + 8].copy_from_slice(&offset.to_le_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(corrupt)?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.next_multiple_of(8).min(self.bytes.len());
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        let len = self.u64()?;
+        // Lengths beyond the data are corrupt, and would otherwise allocate before failing
+        usize::try_from(len)
+            .ok()
+            .filter(|&len| len <= self.bytes.len())
+            .ok_or_else(corrupt)
+    }
+
+    fn block(&mut self) -> Result<&'a [u8], String> {
+        let len = self.len()?;
+        let block = self.bytes(len)?;
+        self.align();
+        Ok(block)
+    }
+
+    /// Length-prefixed numbers, as their `(byte offset, length)`.
+    fn words(&mut self) -> Result<(usize, usize), String> {
+        let len = self.len()?;
+        let start = self.pos;
+        self.bytes(len.checked_mul(8).ok_or_else(corrupt)?)?;
+        Ok((start, len))
+    }
+
+    /// The magic bytes and version, followed by the metadata object.
+    fn header(&mut self) -> Result<JsonObject, String> {
+        if self.bytes(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err("Not a serialized supercluster index".to_string());
+        }
+        let version = self.u32()?;
+        if version != VERSION {
+            return Err(format!(
+                "Unsupported index format version {}, expected {}",
+                version, VERSION
+            ));
+        }
+        self.align();
+        match serde_json::from_slice(self.block()?) {
+            Ok(serde_json::Value::Object(meta)) => Ok(meta),
+            _ => Err("Corrupt index metadata".to_string()),
+        }
+    }
+
+    /// Records written by `Writer::records`, with offsets checked to be ascending and in bounds.
+    fn records(&mut self) -> Result<RecordsAt, String> {
+        let count = self.len()?;
+        let table = self.pos;
+        let offsets = self.bytes(count.checked_add(1).and_then(|n| n.checked_mul(8)).ok_or_else(corrupt)?)?;
+        let mut previous = 0;
+        for chunk in offsets.chunks_exact(8) {
+            let offset = u64::from_le_bytes(chunk.try_into().unwrap());
+            if offset < previous {
+                return Err(corrupt());
+            }
+            previous = offset;
+        }
+        let body = self.pos;
+        self.bytes(usize::try_from(previous).map_err(|_| corrupt())?)?;
+        self.align();
+        Ok(RecordsAt { table, count, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supercluster::ReduceError;
+
+    /// Sums the `value` property of points.
+    #[derive(Debug)]
+    struct Sum;
+
+    impl Reducer for Sum {
+        fn map(&self, properties: &JsonObject) -> Result<JsonObject, ReduceError> {
+            Ok(JsonObject::from_iter([("sum".to_string(), properties["value"].clone())]))
+        }
+
+        fn reduce(&self, accumulated: &mut JsonObject, properties: &JsonObject) -> Result<(), ReduceError> {
+            let sum = accumulated["sum"].as_f64().unwrap() + properties["sum"].as_f64().unwrap();
+            accumulated.insert("sum".to_string(), json!(sum));
+            Ok(())
+        }
+    }
+
+    /// Bytes aligned to 8 bytes, as a memory map is.
+    struct Aligned(Vec<u64>, usize);
+
+    impl AsRef<[u8]> for Aligned {
+        fn as_ref(&self) -> &[u8] {
+            &bytemuck::cast_slice(&self.0)[..self.1]
+        }
+    }
+
+    fn aligned(bytes: &[u8]) -> Bytes {
+        let mut words = vec![0_u64; bytes.len().div_ceil(8)];
+        bytemuck::cast_slice_mut(&mut words)[..bytes.len()].copy_from_slice(bytes);
+        Arc::new(Aligned(words, bytes.len()))
+    }
+
+    fn index(reducer: bool, weighted: bool, radius: bool) -> Supercluster {
+        let mut index = Supercluster::new(Options {
+            reducer: reducer.then(|| Arc::new(Sum) as Arc<dyn Reducer>),
+            weight_property: weighted.then(|| "weight".to_string()),
+            radius_property: radius.then(|| "radius".to_string()),
+            ..Options::default()
+        });
+        let points = (0..200)
+            .map(|i| Feature {
+                geometry: Some(Geometry::new(Point(vec![(i * 37 % 100) as f64 / 10.0, (i * 53 % 100) as f64 / 10.0]))),
+                id: Some(if i % 2 == 0 { Id::Number(i.into()) } else { Id::String(format!("p{}", i)) }),
+                properties: Some(JsonObject::from_iter([
+                    ("value".to_string(), json!(i)),
+                    ("weight".to_string(), json!(1 + i % 3)),
+                    ("radius".to_string(), json!(20 + i % 40)),
+                ])),
+                ..Default::default()
+            })
+            .collect();
+        index.load(points).unwrap();
+        index
+    }
+
+    fn assert_same(expected: &Supercluster, actual: &Supercluster) {
+        assert_eq!(expected.points.to_vec(), actual.points.to_vec());
+        assert_eq!(expected.cluster_props.to_vec(), actual.cluster_props.to_vec());
+        for zoom in 0..=17 {
+            assert_eq!(
+                expected.get_clusters([-180.0, -90.0, 180.0, 90.0], zoom),
+                actual.get_clusters([-180.0, -90.0, 180.0, 90.0], zoom)
+            );
+            let tile = 2_u32.pow(zoom.min(10) as u32) as f64 / 2.0;
+            assert_eq!(expected.get_tile(zoom, tile, tile - 1.0), actual.get_tile(zoom, tile, tile - 1.0));
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (reducer, weighted, radius) in [
+            (false, false, false),
+            (true, false, false),
+            (false, true, false),
+            (false, false, true),
+            (true, true, true),
+        ] {
+            let original = index(reducer, weighted, radius);
+            let bytes = original.to_bytes(&JsonObject::new());
+            let reducer = || original.options.reducer.clone();
+
+            let copied = Supercluster::from_bytes(&bytes, reducer()).unwrap();
+            assert_same(&original, &copied);
+            assert_eq!(copied.to_bytes(&JsonObject::new()), bytes);
+
+            let mapped = Supercluster::from_mapped(aligned(&bytes), reducer()).unwrap();
+            assert_same(&original, &mapped);
+            assert_eq!(mapped.to_bytes(&JsonObject::new()), bytes);
+        }
+    }
+
+    #[test]
+    fn test_extra_meta() {
+        let extra = JsonObject::from_iter([("id_property".to_string(), json!("name"))]);
+        let bytes = index(false, false, false).to_bytes(&extra);
+
+        assert_eq!(Supercluster::read_meta(&bytes).unwrap()["extra"], json!(extra));
+    }
+
+    #[test]
+    fn test_reducer_mismatch() {
+        let with_reducer = index(true, false, false).to_bytes(&JsonObject::new());
+        let without_reducer = index(false, false, false).to_bytes(&JsonObject::new());
+
+        assert!(Supercluster::from_bytes(&with_reducer, None).is_err());
+        assert!(Supercluster::from_bytes(&without_reducer, Some(Arc::new(Sum))).is_err());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = index(true, true, true).to_bytes(&JsonObject::new());
+
+        for len in (0..bytes.len()).step_by(bytes.len() / 500) {
+            assert!(Supercluster::from_bytes(&bytes[..len], Some(Arc::new(Sum))).is_err());
+            assert!(Supercluster::from_mapped(aligned(&bytes[..len]), Some(Arc::new(Sum))).is_err());
+        }
+    }
+
+    #[test]
+    fn test_bad_magic_and_version() {
+        let bytes = index(false, false, false).to_bytes(&JsonObject::new());
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(
+            Supercluster::from_bytes(&bad_magic, None).unwrap_err(),
+            "Not a serialized supercluster index"
+        );
+
+        let mut bad_version = bytes;
+        bad_version[8..12].copy_from_slice(&2_u32.to_le_bytes());
+        assert_eq!(
+            Supercluster::from_bytes(&bad_version, None).unwrap_err(),
+            "Unsupported index format version 2, expected 1"
+        );
+    }
+
+    #[test]
+    fn test_corrupt_references() {
+        let original = index(true, false, false);
+        let bytes = original.to_bytes(&JsonObject::new());
+        let layout = Layout::read(&bytes, original.stride).unwrap();
+        let value_at = |tree: usize, record: usize, offset: usize| {
+            layout.trees[tree].data.0 + 8 * (original.stride * record + offset)
+        };
+
+        // The tree of the input points, and a cluster of the lowest zoom
+        let points = layout.trees.len() - 1;
+        let cluster = (0..layout.trees[0].ids.1)
+            .find(|&record| original.trees[0].data[original.stride * record + OFFSET_NUM] > 1.0)
+            .unwrap();
+
+        for (at, value) in [
+            (value_at(points, 0, OFFSET_ID), 200.0),
+            (value_at(points, 0, OFFSET_ID), 0.5),
+            (value_at(points, 0, OFFSET_ID), -1.0),
+            (value_at(points, 0, OFFSET_NUM), 0.0),
+            (value_at(points, 0, OFFSET_PARENT), 3.0),
+            (value_at(points, 0, OFFSET_PARENT), f64::NAN),
+            (value_at(0, cluster, OFFSET_ID), 199.0),
+            (value_at(0, cluster, OFFSET_ID), 200.0 + (1_000_000 << 5) as f64 + 1.0),
+            (value_at(0, cluster, OFFSET_PROP), original.cluster_props.len() as f64),
+        ] {
+            let mut corrupt = bytes.clone();
+            corrupt[at..at + 8].copy_from_slice(&value.to_le_bytes());
+            assert!(Supercluster::from_bytes(&corrupt, Some(Arc::new(Sum))).is_err());
+            assert!(Supercluster::from_mapped(aligned(&corrupt), Some(Arc::new(Sum))).is_err());
+        }
+    }
+
+    #[test]
+    fn test_corrupt_records() {
+        let original = index(true, false, false);
+        let bytes = original.to_bytes(&JsonObject::new());
+        let layout = Layout::read(&bytes, original.stride).unwrap();
+
+        for (records, byte) in [(&layout.points, 0), (&layout.cluster_props, 0), (&layout.points, 8)] {
+            let mut corrupt = bytes.clone();
+            // An unknown geometry tag, a cluster property record that is not JSON, and a
+            // coordinate count beyond the record
+            corrupt[records.offset(&bytes, 1) + byte] = 0xff;
+            assert!(Supercluster::from_bytes(&corrupt, Some(Arc::new(Sum))).is_err());
+            assert!(Supercluster::from_mapped(aligned(&corrupt), Some(Arc::new(Sum))).is_err());
+        }
+    }
+}
@@ -0,0 +1,628 @@
+//! Reclustering edited points, reusing how a previous index clustered the
+//! points that did not change wherever nothing changed around them.
+//!
+//! Clustering a zoom level processes the points and clusters in order: each
+//! one not merged yet is merged with the neighbors not merged yet, or left as
+//! it is along with them. Processing a point takes the same neighbors as in
+//! the previous index when it matches a previous point that processed itself,
+//! the neighbors it processed then are still unprocessed, and nothing changed
+//! within its radius: no point or cluster appeared or moved, and no previous
+//! point that processed itself had a different outcome, as what it processed
+//! may now be processed by another one or be left for later.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::iter;
+
+use geojson::{Feature, JsonObject};
+
+use super::{ReduceError, Supercluster, OFFSET_ID, OFFSET_PARENT, OFFSET_PROP, OFFSET_ZOOM};
+
+/// How an index clustered its points, kept in memory only.
+#[derive(Clone, Debug)]
+pub(super) struct History {
+    /// For every tree, the position of the point or cluster each of its points and clusters was
+    /// processed with when clustering them at the zoom level below, empty for those not clustered.
+    pub(super) handlers: Vec<Vec<u32>>,
+
+    /// The properties of the clusters before the reducer finished them.
+    pub(super) accumulated: Vec<JsonObject>,
+}
+
+impl Supercluster {
+    /// Load points like `load`, reusing how `previous` clustered the points they have in common.
+    /// Clusters, their IDs and their properties are the same as after loading the points, provided
+    /// `map` and `reduce` always give the same properties for the same points.
+    ///
+    /// # Arguments
+    ///
+    /// - `previous`: An index loaded with the same options.
+    /// - `points`: A vector of GeoJSON features representing input points to be clustered.
+    /// - `origins`: For each point unchanged since `previous` was loaded, the index of the point in
+    ///   `previous`. Unchanged points must keep their order, otherwise every point is reclustered.
+    ///
+    /// # Returns
+    ///
+    /// A mutable reference to the updated `Supercluster` instance, or the error raised by the reducer,
+    /// in which case the instance is left partially loaded.
+    pub fn reload(
+        &mut self,
+        previous: &Supercluster,
+        points: Vec<Feature>,
+        origins: &[Option<usize>],
+    ) -> Result<&mut Self, ReduceError> {
+        let data = self.point_data(&points);
+        let matches = self.point_matches(previous, &data, origins);
+
+        self.index_points(points, data, matches.map(|matches| (previous, matches)))
+    }
+
+    /// Match the data of the points to that of the same points in a previous index.
+    ///
+    /// # Arguments
+    ///
+    /// - `previous`: The previous index.
+    /// - `data`: The data arrays of the points.
+    /// - `origins`: For each unchanged point, the index of the point in `previous`.
+    ///
+    /// # Returns
+    ///
+    /// The position of each point in the data of `previous`, if it is unchanged, or `None` if the
+    /// clustering of `previous` cannot be reused.
+    fn point_matches(&self, previous: &Supercluster, data: &[f64], origins: &[Option<usize>]) -> Option<Vec<Option<u32>>> {
+        previous.history.as_ref()?;
+        if previous.stride != self.stride || previous.trees.len() != self.trees.len() {
+            return None;
+        }
+
+        let mut positions = vec![None; previous.points.len()];
+        for (position, point) in previous.trees.last()?.data.chunks_exact(self.stride).enumerate() {
+            positions[point[OFFSET_ID] as usize] = Some(position as u32);
+        }
+
+        let mut last = None;
+        let mut matches = Vec::with_capacity(data.len() / self.stride);
+        for point in data.chunks_exact(self.stride) {
+            let origin = origins.get(point[OFFSET_ID] as usize).copied().flatten();
+            let position = origin.and_then(|origin| positions.get(origin).copied().flatten());
+
+            // Points in another order would take their neighbors in another order
+            if let Some(position) = position {
+                if last.is_some_and(|last| position <= last) {
+                    return None;
+                }
+                last = Some(position);
+            }
+            matches.push(position);
+        }
+
+        Some(matches)
+    }
+}
+
+/// How a previous index clustered its points and clusters at a zoom level, and which of them
+/// match those being clustered.
+pub(super) struct Level<'a> {
+    /// The previous index.
+    previous: &'a Supercluster,
+
+    /// The zoom level.
+    zoom: u8,
+
+    /// Data of the previous points and clusters.
+    data: &'a [f64],
+
+    /// Data of the points and clusters the previous ones were clustered into.
+    output: &'a [f64],
+
+    /// For every previous point or cluster, the position of the one it was processed with.
+    handlers: &'a [u32],
+
+    /// For every point or cluster, the position of the matching previous one, if any.
+    matches: Vec<Option<u32>>,
+
+    /// For every previous point or cluster, the position of the matching one, if any.
+    positions: Vec<Option<u32>>,
+
+    /// Start of the previous points and clusters processed with each previous one in `processed`.
+    offsets: Vec<u32>,
+
+    /// Positions of the previous points and clusters, grouped by the one they were processed with.
+    processed: Vec<u32>,
+
+    /// For every previous point or cluster that processed itself, the position in `output` of
+    /// the cluster or points it resulted in.
+    starts: Vec<u32>,
+
+    /// Whether the previous points and clusters processed with each one were added to `changed`.
+    marked: Vec<bool>,
+
+    /// Points and clusters around which clustering may differ.
+    changed: Grid,
+
+    /// For every point or cluster clustered so far, the position of the matching one in `output`.
+    output_matches: Vec<Option<u32>>,
+}
+
+impl<'a> Level<'a> {
+    /// Prepare reusing how `previous` clustered its points and clusters at a zoom level.
+    ///
+    /// # Arguments
+    ///
+    /// - `index`: The index being loaded.
+    /// - `previous`: The previous index.
+    /// - `zoom`: The zoom level.
+    /// - `data`: The data of the points and clusters being clustered.
+    /// - `matches`: For every point or cluster, the position of the matching previous one, if any.
+    ///
+    /// # Returns
+    ///
+    /// The clustering of the zoom level, or `None` if `previous` has no history.
+    pub(super) fn new(
+        index: &Supercluster,
+        previous: &'a Supercluster,
+        zoom: u8,
+        data: &[f64],
+        matches: Vec<Option<u32>>,
+    ) -> Option<Self> {
+        let stride = index.stride;
+        let history = previous.history.as_ref()?;
+        let handlers = &history.handlers[(zoom as usize) + 1];
+        let previous_data = &previous.trees[(zoom as usize) + 1].data;
+        let n = handlers.len();
+
+        let mut positions = vec![None; n];
+        for (position, &matched) in matches.iter().enumerate() {
+            if let Some(matched) = matched {
+                positions[matched as usize] = Some(position as u32);
+            }
+        }
+
+        // Group the previous points and clusters by handler, in order
+        let mut offsets = vec![0; n + 1];
+        for &handler in handlers {
+            offsets[(handler as usize) + 1] += 1;
+        }
+        for p in 0..n {
+            offsets[p + 1] += offsets[p];
+        }
+        let mut processed = vec![0; n];
+        let mut filled = offsets.clone();
+        for (p, &handler) in handlers.iter().enumerate() {
+            processed[filled[handler as usize] as usize] = p as u32;
+            filled[handler as usize] += 1;
+        }
+
+        // Replay where the outcome of each one went: a cluster, or the points and clusters left as they are
+        let mut starts = vec![0; n];
+        let mut next = 0;
+        for p in 0..n {
+            if handlers[p] as usize == p {
+                starts[p] = next;
+                next += if previous_data[p * stride + OFFSET_PARENT] == -1.0 {
+                    offsets[p + 1] - offsets[p]
+                } else {
+                    1
+                };
+            }
+        }
+
+        let scale = index.options.extent * (2.0_f64).powi(zoom as i32);
+        let mut level = Level {
+            previous,
+            zoom,
+            data: previous_data,
+            output: &previous.trees[zoom as usize].data,
+            handlers,
+            matches,
+            positions,
+            offsets,
+            processed,
+            starts,
+            marked: vec![false; n],
+            changed: Grid::new(index.radius_at(zoom) / scale),
+            output_matches: vec![],
+        };
+
+        for (position, matched) in level.matches.iter().enumerate() {
+            if matched.is_none() {
+                level.changed.insert(data[position * stride], data[position * stride + 1]);
+            }
+        }
+        for p in 0..n {
+            if level.handlers[p] as usize == p && level.positions[p].is_none() {
+                level.mark(p);
+            }
+        }
+
+        Some(level)
+    }
+
+    /// Reuse the outcome of processing a point or cluster in the previous index, if it is the same.
+    ///
+    /// # Arguments
+    ///
+    /// - `index`: The index being loaded.
+    /// - `data`: The data of the points and clusters being clustered.
+    /// - `i`: The position of the point or cluster being processed.
+    /// - `next`: The position in the data of the next zoom level of what it results in.
+    ///
+    /// # Returns
+    ///
+    /// The neighbors to process it with, and the accumulated properties of the cluster they form
+    /// if it has any; or `None` if they must be searched for.
+    pub(super) fn reuse(
+        &mut self,
+        index: &Supercluster,
+        data: &[f64],
+        i: usize,
+        next: usize,
+    ) -> Option<(Vec<usize>, Option<JsonObject>)> {
+        let stride = index.stride;
+        let p = self.matches[i]? as usize;
+        if self.handlers[p] as usize != p {
+            return None;
+        }
+
+        let mut neighbor_ids = vec![];
+        for &q in self.members(p) {
+            if q as usize == p {
+                continue;
+            }
+            let k = self.positions[q as usize]? as usize;
+            if data[k * stride + OFFSET_ZOOM] <= (self.zoom as f64) {
+                return None;
+            }
+            neighbor_ids.push(k);
+        }
+
+        let scale = index.options.extent * (2.0_f64).powi(self.zoom as i32);
+        let radius = index.radius(data, i * stride, self.zoom) / scale;
+        if self.changed.any_within(data[i * stride], data[i * stride + 1], radius) {
+            return None;
+        }
+
+        let start = self.starts[p];
+        let clustered = self.data[p * stride + OFFSET_PARENT] != -1.0;
+        let properties = match (clustered, &index.options.reducer, &self.previous.history) {
+            (true, Some(_), Some(history)) => {
+                Some(history.accumulated[self.output[(start as usize) * stride + OFFSET_PROP] as usize].clone())
+            }
+            _ => None,
+        };
+        let count = if clustered { 1 } else { self.members(p).len() as u32 };
+
+        self.output_matches.resize(next, None);
+        self.output_matches.extend((start..start + count).map(Some));
+
+        neighbor_ids.sort_unstable();
+        Some((neighbor_ids, properties))
+    }
+
+    /// Record that a point or cluster was processed without reusing the previous outcome.
+    ///
+    /// # Arguments
+    ///
+    /// - `i`: The position of the point or cluster.
+    /// - `visited`: The positions of the neighbors processed with it.
+    pub(super) fn processed(&mut self, i: usize, visited: impl Iterator<Item = usize>) {
+        for k in iter::once(i).chain(visited) {
+            if let Some(p) = self.matches[k] {
+                if self.handlers[p as usize] == p {
+                    self.mark(p as usize);
+                }
+            }
+        }
+    }
+
+    /// Finish the zoom level.
+    ///
+    /// # Arguments
+    ///
+    /// - `len`: The number of points and clusters in the data of the next zoom level.
+    ///
+    /// # Returns
+    ///
+    /// The previous index, and for every point or cluster of the next zoom level, the position of
+    /// the matching previous one, if any.
+    pub(super) fn into_output_matches(mut self, len: usize) -> (&'a Supercluster, Vec<Option<u32>>) {
+        self.output_matches.resize(len, None);
+        (self.previous, self.output_matches)
+    }
+
+    /// The previous points and clusters processed with a previous point or cluster, itself included.
+    fn members(&self, p: usize) -> &[u32] {
+        &self.processed[self.offsets[p] as usize..self.offsets[p + 1] as usize]
+    }
+
+    /// Mark the area of the points and clusters processed with a previous point or cluster as changed.
+    fn mark(&mut self, p: usize) {
+        if self.marked[p] {
+            return;
+        }
+        self.marked[p] = true;
+
+        let stride = self.previous.stride;
+        for &q in &self.processed[self.offsets[p] as usize..self.offsets[p + 1] as usize] {
+            let k = (q as usize) * stride;
+            self.changed.insert(self.data[k], self.data[k + 1]);
+        }
+    }
+}
+
+/// Points bucketed by cell, to find whether any lies within a radius of a point.
+struct Grid {
+    /// Side of the cells.
+    cell: f64,
+
+    /// Points by cell.
+    cells: HashMap<(i64, i64), Vec<[f64; 2]>, BuildHasherDefault<CellHasher>>,
+
+    /// Bounding box of the points, as `[min_x, min_y, max_x, max_y]`.
+    bbox: [f64; 4],
+}
+
+impl Grid {
+    fn new(cell: f64) -> Self {
+        Grid {
+            cell: if cell > 0.0 && cell.is_finite() { cell } else { 1.0 },
+            cells: HashMap::default(),
+            bbox: [f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY],
+        }
+    }
+
+    fn key(&self, x: f64, y: f64) -> (i64, i64) {
+        ((x / self.cell).floor() as i64, (y / self.cell).floor() as i64)
+    }
+
+    fn insert(&mut self, x: f64, y: f64) {
+        self.bbox = [self.bbox[0].min(x), self.bbox[1].min(y), self.bbox[2].max(x), self.bbox[3].max(y)];
+        self.cells.entry(self.key(x, y)).or_default().push([x, y]);
+    }
+
+    /// Whether any point lies within `radius` of `(x, y)`, measured like `KDBush::within`.
+    fn any_within(&self, x: f64, y: f64, radius: f64) -> bool {
+        // Leave some slack for rounding when ruling points out before measuring their distance
+        let reach = radius * 1.01 + 1e-12;
+        if self.cells.is_empty()
+            || x + reach < self.bbox[0]
+            || y + reach < self.bbox[1]
+            || x - reach > self.bbox[2]
+            || y - reach > self.bbox[3]
+        {
+            return false;
+        }
+
+        let r2 = radius * radius;
+        let near = |&[px, py]: &[f64; 2]| {
+            let dx = px - x;
+            let dy = py - y;
+            dx * dx + dy * dy <= r2
+        };
+
+        let (min_x, min_y) = self.key(x - reach, y - reach);
+        let (max_x, max_y) = self.key(x + reach, y + reach);
+        let span = (max_x as f64 - min_x as f64 + 1.0) * (max_y as f64 - min_y as f64 + 1.0);
+        if span > self.cells.len() as f64 {
+            return self.cells.values().flatten().any(near);
+        }
+
+        (min_x..=max_x).any(|cx| {
+            (min_y..=max_y).any(|cy| self.cells.get(&(cx, cy)).is_some_and(|points| points.iter().any(near)))
+        })
+    }
+}
+
+/// Hashes cell coordinates with a multiplication, as the default hasher would cost more than
+/// querying the points.
+#[derive(Default)]
+struct CellHasher(u64);
+
+impl Hasher for CellHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn write_i64(&mut self, n: i64) {
+        self.write_u64(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supercluster::{Options, Reducer};
+    use geojson::{Geometry, Value::Point};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Lists the `value` property of points in the order they are merged, counting the points mapped.
+    #[derive(Debug, Default)]
+    struct Collect {
+        mapped: AtomicUsize,
+    }
+
+    impl Reducer for Collect {
+        fn map(&self, properties: &JsonObject) -> Result<JsonObject, ReduceError> {
+            self.mapped.fetch_add(1, Ordering::Relaxed);
+            Ok(JsonObject::from_iter([("values".to_string(), json!([properties["value"]]))]))
+        }
+
+        fn reduce(&self, accumulated: &mut JsonObject, properties: &JsonObject) -> Result<(), ReduceError> {
+            let values = properties["values"].as_array().unwrap().clone();
+            accumulated["values"].as_array_mut().unwrap().extend(values);
+            Ok(())
+        }
+
+        fn finish(&self, accumulated: &mut JsonObject) {
+            let count = accumulated["values"].as_array().unwrap().len();
+            accumulated.insert("count".to_string(), json!(count));
+        }
+    }
+
+    fn random(seed: &mut u64) -> f64 {
+        *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*seed >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// A point around one of a few centers, every fourth one without a radius.
+    fn point(value: usize, seed: &mut u64) -> Feature {
+        let center = (random(seed) * 5.0).floor();
+        let x = center * 20.0 - 50.0 + random(seed) * random(seed) * 30.0;
+        let y = center * 10.0 - 25.0 + random(seed) * random(seed) * 30.0;
+        let mut properties = JsonObject::from_iter([
+            ("value".to_string(), json!(value)),
+            ("weight".to_string(), json!(1 + value % 3)),
+        ]);
+        if !value.is_multiple_of(4) {
+            properties.insert("radius".to_string(), json!(20 + value % 50));
+        }
+        Feature {
+            geometry: Some(Geometry::new(Point(vec![x, y]))),
+            properties: Some(properties),
+            ..Default::default()
+        }
+    }
+
+    fn options(reducer: &Arc<Collect>) -> Options {
+        Options {
+            reducer: Some(reducer.clone()),
+            ..Options::default()
+        }
+    }
+
+    fn load(options: &Options, points: Vec<Feature>) -> Supercluster {
+        let mut index = Supercluster::new(options.clone());
+        index.load(points).unwrap();
+        index
+    }
+
+    fn reload(options: &Options, previous: &Supercluster, points: Vec<Feature>, origins: &[Option<usize>]) -> Supercluster {
+        let mut index = Supercluster::new(options.clone());
+        index.reload(previous, points, origins).unwrap();
+        index
+    }
+
+    /// Assert that two indexes have the same points, clusters, cluster IDs and cluster properties.
+    fn assert_same(actual: &Supercluster, expected: &Supercluster) {
+        assert_eq!(actual.points.to_vec(), expected.points.to_vec());
+        assert_eq!(actual.cluster_props.to_vec(), expected.cluster_props.to_vec());
+        for (zoom, (actual, expected)) in actual.trees.iter().zip(&expected.trees).enumerate() {
+            let bits = |data: &[f64]| data.iter().map(|value| value.to_bits()).collect::<Vec<_>>();
+            assert_eq!(bits(&actual.data), bits(&expected.data), "tree {}", zoom);
+        }
+    }
+
+    /// Edit points a few times, removing, moving and adding some, and check that reloading them
+    /// gives the same index as loading them.
+    fn check_edits(adjust: impl Fn(&mut Options)) {
+        let reducer = Arc::new(Collect::default());
+        let mut options = options(&reducer);
+        adjust(&mut options);
+
+        let mut seed = 7;
+        let mut points: Vec<Feature> = (0..1000).map(|value| point(value, &mut seed)).collect();
+        let mut index = load(&options, points.clone());
+        let mut next_value = points.len();
+
+        for _ in 0..3 {
+            let mut edited = vec![];
+            let mut origins = vec![];
+            for (i, feature) in points.iter().enumerate() {
+                match (random(&mut seed) * 40.0) as usize {
+                    0 => {}
+                    1 => {
+                        let value = feature.property("value").unwrap().as_u64().unwrap() as usize;
+                        edited.push(point(value, &mut seed));
+                        origins.push(None);
+                    }
+                    _ => {
+                        edited.push(feature.clone());
+                        origins.push(Some(i));
+                    }
+                }
+            }
+            for _ in 0..20 {
+                edited.push(point(next_value, &mut seed));
+                origins.push(None);
+                next_value += 1;
+            }
+
+            let reloaded = reload(&options, &index, edited.clone(), &origins);
+            assert_same(&reloaded, &load(&options, edited.clone()));
+            index = reloaded;
+            points = edited;
+        }
+    }
+
+    #[test]
+    fn test_reload_matches_load() {
+        check_edits(|_| {});
+    }
+
+    #[test]
+    fn test_reload_matches_load_with_options() {
+        check_edits(|options| options.min_points = 3);
+        check_edits(|options| options.radius_schedule = vec![(3, 80.0), (9, 20.0)]);
+        check_edits(|options| options.stable_ids = true);
+        check_edits(|options| {
+            options.weight_property = Some("weight".to_string());
+            options.radius_property = Some("radius".to_string());
+        });
+        check_edits(|options| {
+            options.reducer = None;
+            options.min_zoom = 4;
+            options.max_zoom = 12;
+        });
+    }
+
+    #[test]
+    fn test_reload_reclusters_around_edits_only() {
+        let reducer = Arc::new(Collect::default());
+        let options = options(&reducer);
+        let mut seed = 11;
+        let mut points: Vec<Feature> = (0..5000).map(|value| point(value, &mut seed)).collect();
+        let index = load(&options, points.clone());
+        let loaded = reducer.mapped.swap(0, Ordering::Relaxed);
+
+        points[1234] = point(1234, &mut seed);
+        let mut origins: Vec<Option<usize>> = (0..points.len()).map(Some).collect();
+        origins[1234] = None;
+        let reloaded = reload(&options, &index, points.clone(), &origins);
+
+        assert!(reducer.mapped.load(Ordering::Relaxed) * 10 < loaded);
+        assert_same(&reloaded, &load(&options, points));
+    }
+
+    #[test]
+    fn test_reload_without_history() {
+        let reducer = Arc::new(Collect::default());
+        let options = options(&reducer);
+        let mut seed = 3;
+        let points: Vec<Feature> = (0..500).map(|value| point(value, &mut seed)).collect();
+        let index = load(&options, points.clone());
+        let restored = Supercluster::from_bytes(&index.to_bytes(&JsonObject::new()), options.reducer.clone()).unwrap();
+
+        let mut edited = points[1..].to_vec();
+        edited.push(point(500, &mut seed));
+        let mut origins: Vec<Option<usize>> = (1..500).map(Some).collect();
+        origins.push(None);
+        assert_same(&reload(&options, &restored, edited.clone(), &origins), &load(&options, edited.clone()));
+
+        // Unchanged points in another order
+        edited.reverse();
+        origins.reverse();
+        assert_same(&reload(&options, &index, edited.clone(), &origins), &load(&options, edited));
+    }
+}
@@ -0,0 +1,833 @@
+//! Queries restricted to a selection of the points of an index, such as those matching a filter.
+//! They reuse the clustering of all points rather than clustering the selected ones again: a cluster
+//! stands for its selected points only, with their number, weighted center, weight and properties,
+//! clusters without selected points are left out, and those with a single one are that point.
+
+use super::{
+    get_cluster_json, get_cluster_properties, ClusterPoint, CoordinateSystem, ReduceError, Supercluster,
+    OFFSET_ID, OFFSET_NUM, OFFSET_PARENT,
+};
+use super::store::Records;
+use geojson::{feature::Id, Feature, FeatureCollection, Geometry, JsonObject, Value::Point};
+use std::collections::HashMap;
+
+/// A selection of the points of an index, with its clusters reduced to them.
+#[derive(Debug)]
+pub struct Selection {
+    /// Whether each loaded point is selected.
+    selected: Vec<bool>,
+
+    /// Offsets in `data` of the clusters with selected points, by cluster ID.
+    clusters: HashMap<usize, usize>,
+
+    /// Data arrays of the reduced clusters, laid out like those of the index; a cluster with a single
+    /// selected point has the data of that point.
+    data: Vec<f64>,
+
+    /// Properties of the reduced clusters.
+    cluster_props: Records<JsonObject>,
+}
+
+/// A cluster being reduced to its selected points.
+struct Reduced {
+    id: usize,
+    first: Vec<f64>,
+    count: f64,
+    weight: f64,
+    wx: f64,
+    wy: f64,
+    properties: Option<JsonObject>,
+}
+
+/// An index queried for a selection of its points, or for all of them.
+#[derive(Clone, Copy, Debug)]
+pub struct Selected<'a> {
+    index: &'a Supercluster,
+    selection: Option<&'a Selection>,
+}
+
+impl Supercluster {
+    /// Reduce the clusters of the index to a selection of its points, in a single pass over the
+    /// clusters from the highest zoom level down.
+    ///
+    /// # Arguments
+    ///
+    /// - `selected`: Whether each loaded point is selected.
+    ///
+    /// # Returns
+    ///
+    /// The selection, or the error raised by the reducer.
+    pub fn select(&self, selected: Vec<bool>) -> Result<Selection, ReduceError> {
+        let reducer = self.options.reducer.as_deref();
+        let mut clusters = HashMap::new();
+        let mut data = vec![];
+        let mut cluster_props = vec![];
+
+        for zoom in (self.options.min_zoom..=self.options.max_zoom).rev() {
+            let level = &self.trees[(zoom as usize) + 1].data;
+            let mut reduced: Vec<Reduced> = vec![];
+            let mut positions = HashMap::new();
+
+            // Members are in the order they were merged in, starting with the origin of their
+            // cluster, and the clusters among them were formed at higher zoom levels
+            for k in (0..level.len()).step_by(self.stride) {
+                let parent = level[k + OFFSET_PARENT];
+                if parent < 0.0 {
+                    continue;
+                }
+
+                let member = match reduce(&level[k..k + self.stride], &selected, &clusters, &data) {
+                    Some(member) => member,
+                    None => continue,
+                };
+                let weight = self.weight(member, 0);
+                let properties = match reducer {
+                    Some(reducer) => Some(self.map_properties(reducer, member, 0, &cluster_props)?),
+                    None => None,
+                };
+
+                let position = *positions.entry(parent as usize).or_insert(reduced.len());
+                if position == reduced.len() {
+                    reduced.push(Reduced {
+                        id: parent as usize,
+                        first: member.to_vec(),
+                        count: member[OFFSET_NUM],
+                        weight,
+                        wx: member[0] * weight,
+                        wy: member[1] * weight,
+                        properties: properties.map(|properties| properties.into_owned()),
+                    });
+                    continue;
+                }
+
+                let cluster = &mut reduced[position];
+                cluster.count += member[OFFSET_NUM];
+                cluster.weight += weight;
+                cluster.wx += member[0] * weight;
+                cluster.wy += member[1] * weight;
+
+                if let (Some(reducer), Some(accumulated), Some(properties)) =
+                    (reducer, cluster.properties.as_mut(), properties)
+                {
+                    reducer.reduce(accumulated, &properties)?;
+                }
+            }
+
+            for cluster in reduced {
+                clusters.insert(cluster.id, data.len());
+
+                if cluster.count == 1.0 {
+                    data.extend(cluster.first);
+                    continue;
+                }
+
+                data.push(cluster.wx / cluster.weight);
+                data.push(cluster.wy / cluster.weight);
+                data.push(f64::INFINITY);
+                data.push(cluster.id as f64);
+                data.push(-1.0);
+                data.push(cluster.count);
+
+                if let Some(properties) = cluster.properties {
+                    data.push(cluster_props.len() as f64);
+                    cluster_props.push(properties);
+                }
+
+                if self.offset_weight.is_some() {
+                    data.push(cluster.weight);
+                }
+
+                // Only the clusters of the index are searched for children, with their own radius
+                if self.offset_radius.is_some() {
+                    data.push(f64::NAN);
+                }
+            }
+        }
+
+        if let Some(reducer) = reducer {
+            for properties in &mut cluster_props {
+                reducer.finish(properties);
+            }
+        }
+
+        Ok(Selection {
+            selected,
+            clusters,
+            data,
+            cluster_props: cluster_props.into(),
+        })
+    }
+
+    /// Query the clusters of a selection of the points.
+    ///
+    /// # Arguments
+    ///
+    /// - `selection`: The selection returned by `Supercluster::select`, or `None` for all points.
+    ///
+    /// # Returns
+    ///
+    /// The index restricted to the selection.
+    pub fn with_selection<'a>(&'a self, selection: Option<&'a Selection>) -> Selected<'a> {
+        Selected {
+            index: self,
+            selection,
+        }
+    }
+}
+
+/// The queries of all points, which the tests of the index run.
+#[cfg(test)]
+impl Supercluster {
+    pub fn get_clusters(&self, bbox: [f64; 4], zoom: u8) -> Vec<Feature> {
+        self.with_selection(None).get_clusters(bbox, zoom)
+    }
+
+    pub fn get_children(&self, cluster_id: usize) -> Result<Vec<Feature>, &'static str> {
+        self.with_selection(None).get_children(cluster_id)
+    }
+
+    pub fn get_leaves(&self, cluster_id: usize, limit: usize, offset: usize) -> Vec<Feature> {
+        self.with_selection(None).get_leaves(cluster_id, limit, offset)
+    }
+
+    pub fn get_tile(&self, z: u8, x: f64, y: f64) -> Option<FeatureCollection> {
+        self.with_selection(None).get_tile(z, x, y)
+    }
+
+    pub fn get_cluster_expansion_zoom(&self, cluster_id: usize) -> usize {
+        self.with_selection(None).get_cluster_expansion_zoom(cluster_id)
+    }
+}
+
+/// Reduce a point or cluster to the selected points.
+///
+/// # Arguments
+///
+/// - `record`: The data arrays of the point or cluster in the index.
+/// - `selected`: Whether each loaded point is selected.
+/// - `clusters`: The offsets in `data` of the reduced clusters by cluster ID.
+/// - `data`: The data arrays of the reduced clusters.
+///
+/// # Returns
+///
+/// The data arrays of the point or reduced cluster, or `None` if it has no selected point.
+fn reduce<'a>(
+    record: &'a [f64],
+    selected: &[bool],
+    clusters: &HashMap<usize, usize>,
+    data: &'a [f64],
+) -> Option<&'a [f64]> {
+    if record[OFFSET_NUM] > 1.0 {
+        let offset = *clusters.get(&(record[OFFSET_ID] as usize))?;
+        Some(&data[offset..offset + record.len()])
+    } else if selected[record[OFFSET_ID] as usize] {
+        Some(record)
+    } else {
+        None
+    }
+}
+
+impl<'a> Selected<'a> {
+    /// Get the data arrays of a point or cluster of the index for the selection.
+    fn record(&self, data: &'a [f64], k: usize) -> Option<&'a [f64]> {
+        let record = &data[k..k + self.index.stride];
+
+        match self.selection {
+            Some(selection) => reduce(record, &selection.selected, &selection.clusters, &selection.data),
+            None => Some(record),
+        }
+    }
+
+    /// Get the properties of the clusters of the selection.
+    fn cluster_props(&self) -> &'a Records<JsonObject> {
+        self.selection
+            .map_or(&self.index.cluster_props, |selection| &selection.cluster_props)
+    }
+
+    /// Retrieve clustered features within the specified bounding box and zoom level.
+    ///
+    /// # Arguments
+    ///
+    /// - `bbox`: The bounding box as an array of four coordinates [min_lng, min_lat, max_lng, max_lat].
+    /// - `zoom`: The zoom level at which to retrieve clusters.
+    ///
+    /// # Returns
+    ///
+    /// A vector of GeoJSON features representing the clusters within the specified bounding box and zoom level.
+    pub fn get_clusters(&self, bbox: [f64; 4], zoom: u8) -> Vec<Feature> {
+        let index = self.index;
+        let tree = &index.trees[index.limit_zoom(zoom)];
+        let mut clusters = Vec::new();
+
+        for k in index.range_offsets(bbox, zoom) {
+            let data = match self.record(&tree.data, k) {
+                Some(data) => data,
+                None => continue,
+            };
+
+            clusters.push(if data[OFFSET_NUM] > 1.0 {
+                get_cluster_json(
+                    data,
+                    0,
+                    self.cluster_props(),
+                    index.offset_weight,
+                    index.options.coordinate_system,
+                )
+            } else {
+                index.points.get(data[OFFSET_ID] as usize).into_owned()
+            });
+        }
+
+        clusters
+    }
+
+    /// Retrieve the clusters and points within the specified bounding box and zoom level as plain records,
+    /// without building GeoJSON features for them.
+    ///
+    /// # Arguments
+    ///
+    /// - `bbox`: The bounding box as an array of four coordinates [min_lng, min_lat, max_lng, max_lat].
+    /// - `zoom`: The zoom level at which to retrieve clusters.
+    ///
+    /// # Returns
+    ///
+    /// A vector of `ClusterPoint` records in the same order `get_clusters` returns features.
+    pub fn get_cluster_points(&self, bbox: [f64; 4], zoom: u8) -> Vec<ClusterPoint> {
+        let index = self.index;
+        let tree = &index.trees[index.limit_zoom(zoom)];
+        let mut clusters = Vec::new();
+
+        for k in index.range_offsets(bbox, zoom) {
+            let data = match self.record(&tree.data, k) {
+                Some(data) => data,
+                None => continue,
+            };
+
+            clusters.push(if data[OFFSET_NUM] > 1.0 {
+                let (lng, lat) = index.options.coordinate_system.unproject(data[0], data[1]);
+                ClusterPoint {
+                    lng,
+                    lat,
+                    cluster_id: Some(data[OFFSET_ID] as usize),
+                    point_count: data[OFFSET_NUM] as usize,
+                    point_index: None,
+                }
+            } else {
+                let point_index = data[OFFSET_ID] as usize;
+                let point = index.points.get(point_index);
+                let (lng, lat) = match point.geometry.as_ref().map(|g| &g.value) {
+                    Some(Point(coords)) => (coords[0], coords[1]),
+                    _ => index.options.coordinate_system.unproject(data[0], data[1]),
+                };
+
+                ClusterPoint {
+                    lng,
+                    lat,
+                    cluster_id: None,
+                    point_count: 1,
+                    point_index: Some(point_index),
+                }
+            });
+        }
+
+        clusters
+    }
+
+    /// Retrieve the cluster features for a specified cluster ID.
+    ///
+    /// # Arguments
+    ///
+    /// - `cluster_id`: The unique identifier of the cluster.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a vector of GeoJSON features representing the children of the specified cluster if successful,
+    /// or an error message if the cluster is not found. Clusters without selected points have no children.
+    pub fn get_children(&self, cluster_id: usize) -> Result<Vec<Feature>, &'static str> {
+        let index = self.index;
+        let origin_id = index.get_origin_id(cluster_id);
+        let origin_zoom = index.get_origin_zoom(cluster_id);
+        let error_msg = "No cluster with the specified id.";
+        let tree = index.trees.get(origin_zoom);
+
+        if tree.is_none() {
+            return Err(error_msg);
+        }
+
+        let tree = tree.expect("tree is not defined");
+        let data = &tree.data;
+
+        if origin_id * index.stride >= data.len() {
+            return Err(error_msg);
+        }
+
+        // The cluster was formed at the zoom below the tree, around its origin
+        let r = index.radius(data, origin_id * index.stride, (origin_zoom as u8).saturating_sub(1))
+            / (index.options.extent * f64::powf(2.0, (origin_zoom as f64) - 1.0));
+
+        let x = data[origin_id * index.stride];
+        let y = data[origin_id * index.stride + 1];
+
+        let ids = tree.within(x, y, r);
+
+        let mut found = false;
+        let mut children = Vec::new();
+
+        for id in ids {
+            let k = id * index.stride;
+
+            if data[k + OFFSET_PARENT] == (cluster_id as f64) {
+                found = true;
+
+                let child = match self.record(data, k) {
+                    Some(child) => child,
+                    None => continue,
+                };
+
+                if child[OFFSET_NUM] > 1.0 {
+                    children.push(get_cluster_json(
+                        child,
+                        0,
+                        self.cluster_props(),
+                        index.offset_weight,
+                        index.options.coordinate_system,
+                    ));
+                } else {
+                    let point_id = child[OFFSET_ID] as usize;
+
+                    children.push(index.points.get(point_id).into_owned());
+                }
+            }
+        }
+
+        if !found {
+            return Err(error_msg);
+        }
+
+        Ok(children)
+    }
+
+    /// Retrieve individual leaf features within a cluster.
+    ///
+    /// # Arguments
+    ///
+    /// - `cluster_id`: The unique identifier of the cluster.
+    /// - `limit`: The maximum number of leaf features to retrieve.
+    /// - `offset`: The offset to start retrieving leaf features.
+    ///
+    /// # Returns
+    ///
+    /// A vector of GeoJSON features representing the individual leaf features within the cluster.
+    pub fn get_leaves(&self, cluster_id: usize, limit: usize, offset: usize) -> Vec<Feature> {
+        let mut leaves = vec![];
+
+        self.append_leaves(&mut leaves, cluster_id, limit, offset, 0);
+
+        leaves
+    }
+
+    /// Retrieve a vector of features within a tile at the given zoom level and tile coordinates.
+    ///
+    /// # Arguments
+    ///
+    /// - `z`: The zoom level of the tile.
+    /// - `x`: The X coordinate of the tile.
+    /// - `y`: The Y coordinate of the tile.
+    ///
+    /// # Returns
+    ///
+    /// An optional `Tile` containing a vector of GeoJSON features within the specified tile, or `None` if there are no features.
+    pub fn get_tile(&self, z: u8, x: f64, y: f64) -> Option<FeatureCollection> {
+        let index = self.index;
+        let tree = &index.trees[index.limit_zoom(z)];
+        let z2: f64 = (2u32).pow(z as u32) as f64;
+        // Clusters and points may be drawn as large as their radius, so the tile takes in those
+        // that far outside it
+        let p = index.radius_at(z).max(index.max_point_radius) / index.options.extent;
+        let top = (y - p) / z2;
+        let bottom = (y + 1.0 + p) / z2;
+
+        let mut tile = FeatureCollection {
+            bbox: None,
+            foreign_members: None,
+            features: vec![],
+        };
+
+        let ids = tree.range((x - p) / z2, top, (x + 1.0 + p) / z2, bottom);
+
+        self.add_tile_features(&ids, &tree.data, x, y, z2, &mut tile);
+
+        let wraps = index.options.coordinate_system == CoordinateSystem::Geographic;
+
+        if wraps && x == 0.0 {
+            let ids = tree.range(1.0 - p / z2, top, 1.0, bottom);
+
+            self.add_tile_features(&ids, &tree.data, z2, y, z2, &mut tile);
+        }
+
+        if wraps && x == z2 - 1.0 {
+            let ids = tree.range(0.0, top, p / z2, bottom);
+
+            self.add_tile_features(&ids, &tree.data, -1.0, y, z2, &mut tile);
+        }
+
+        if tile.features.is_empty() {
+            None
+        } else {
+            Some(tile)
+        }
+    }
+
+    /// Determine the zoom level at which a specific cluster expands.
+    ///
+    /// # Arguments
+    ///
+    /// - `cluster_id`: The unique identifier of the cluster.
+    ///
+    /// # Returns
+    ///
+    /// The zoom level at which the cluster expands.
+    pub fn get_cluster_expansion_zoom(&self, mut cluster_id: usize) -> usize {
+        let mut expansion_zoom = self.index.get_origin_zoom(cluster_id) - 1;
+
+        while expansion_zoom <= (self.index.options.max_zoom as usize) {
+            let children = match self.get_children(cluster_id) {
+                Ok(children) => children,
+                Err(_) => break,
+            };
+
+            expansion_zoom += 1;
+
+            if children.len() != 1 {
+                break;
+            }
+
+            cluster_id = match children[0].property("cluster_id") {
+                Some(property) => match property.as_u64() {
+                    Some(id) => id as usize,
+                    None => break,
+                },
+                None => break,
+            };
+        }
+
+        expansion_zoom
+    }
+
+    /// Appends leaves (features) to the result vector based on the specified criteria.
+    ///
+    /// # Arguments
+    ///
+    /// - `result`: A mutable reference to a vector where leaves will be appended.
+    /// - `cluster_id`: The identifier of the cluster whose leaves are being collected.
+    /// - `limit`: The maximum number of leaves to collect.
+    /// - `offset`: The number of leaves to skip before starting to collect.
+    /// - `skipped`: The current count of skipped leaves, used for tracking the progress.
+    ///
+    /// # Returns
+    ///
+    /// The updated count of skipped leaves after processing the current cluster.
+    fn append_leaves(
+        &self,
+        result: &mut Vec<Feature>,
+        cluster_id: usize,
+        limit: usize,
+        offset: usize,
+        mut skipped: usize,
+    ) -> usize {
+        let cluster = match self.get_children(cluster_id) {
+            Ok(cluster) => cluster,
+            Err(_) => return skipped,
+        };
+
+        for child in cluster {
+            if result.len() >= limit {
+                break;
+            }
+
+            if child.contains_property("cluster") {
+                if let Some(point_count) = child.property("point_count").and_then(|p| p.as_i64()) {
+                    if skipped + point_count as usize <= offset {
+                        // Skip the whole cluster
+                        skipped += point_count as usize;
+                    } else {
+                        // Enter the cluster
+                        if let Some(cluster_id) =
+                            child.property("cluster_id").and_then(|c| c.as_u64())
+                        {
+                            skipped = self.append_leaves(
+                                result,
+                                cluster_id as usize,
+                                limit,
+                                offset,
+                                skipped,
+                            );
+                        }
+                        // Exit the cluster
+                    }
+                }
+            } else if skipped < offset {
+                // Skip a single point
+                skipped += 1;
+            } else {
+                // Add a single point
+                result.push(child);
+            }
+        }
+
+        skipped
+    }
+
+    /// Populate a tile with features based on the specified point IDs, data, and tile parameters.
+    ///
+    /// # Arguments
+    ///
+    /// - `ids`: A vector of point IDs used for populating the tile.
+    /// - `data`: A reference to the flat numeric arrays representing point data.
+    /// - `x`: The X coordinate of the tile.
+    /// - `y`: The Y coordinate of the tile.
+    /// - `z2`: The zoom level multiplied by 2.
+    /// - `tile`: A mutable reference to the `FeatureCollection` to be populated with features.
+    fn add_tile_features(
+        &self,
+        ids: &Vec<usize>,
+        data: &[f64],
+        x: f64,
+        y: f64,
+        z2: f64,
+        tile: &mut FeatureCollection,
+    ) {
+        let index = self.index;
+
+        for i in ids {
+            let record = match self.record(data, i * index.stride) {
+                Some(record) => record,
+                None => continue,
+            };
+            let is_cluster = record[OFFSET_NUM] > 1.0;
+
+            let px;
+            let py;
+            let properties;
+            let id;
+
+            if is_cluster {
+                properties =
+                    get_cluster_properties(record, 0, self.cluster_props(), index.offset_weight);
+
+                px = record[0];
+                py = record[1];
+                id = Some(Id::String(record[OFFSET_ID].to_string()));
+            } else {
+                let p = index.points.get(record[OFFSET_ID] as usize);
+                properties = p.properties.clone().unwrap_or_default();
+
+                match p.geometry.as_ref() {
+                    Some(geometry) => {
+                        if let Point(coordinates) = &geometry.value {
+                            (px, py) = index
+                                .options
+                                .coordinate_system
+                                .project(coordinates[0], coordinates[1]);
+                        } else {
+                            // Other geometries were indexed at their anchor
+                            px = record[0];
+                            py = record[1];
+                        }
+                    }
+                    None => continue, // Handle the case where geometry is None
+                }
+
+                id = p.id.clone();
+            }
+
+            let geometry = Geometry::new(Point(vec![
+                (index.options.extent * (px * z2 - x)).round(),
+                (index.options.extent * (py * z2 - y)).round(),
+            ]));
+
+            tile.features.push(Feature {
+                id,
+                bbox: None,
+                foreign_members: None,
+                geometry: Some(geometry),
+                properties: Some(properties),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supercluster::{lat_y, lng_x, Options, Reducer};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    const WORLD: [f64; 4] = [-180.0, -90.0, 180.0, 90.0];
+
+    /// Sums the `value` property of points.
+    #[derive(Debug)]
+    struct Sum;
+
+    impl Reducer for Sum {
+        fn map(&self, properties: &JsonObject) -> Result<JsonObject, ReduceError> {
+            Ok(JsonObject::from_iter([("sum".to_string(), properties["value"].clone())]))
+        }
+
+        fn reduce(&self, accumulated: &mut JsonObject, properties: &JsonObject) -> Result<(), ReduceError> {
+            let sum = accumulated["sum"].as_u64().unwrap() + properties["sum"].as_u64().unwrap();
+            accumulated.insert("sum".to_string(), json!(sum));
+            Ok(())
+        }
+
+        fn finish(&self, accumulated: &mut JsonObject) {
+            accumulated.insert("finished".to_string(), json!(true));
+        }
+    }
+
+    fn random(seed: &mut u64) -> f64 {
+        *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*seed >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// An index of weighted points around a few centers, whose `value` is their index.
+    fn index() -> Supercluster {
+        let mut seed = 7;
+        let points = (0..600)
+            .map(|value| {
+                let center = (random(&mut seed) * 4.0).floor();
+                let x = center * 30.0 - 60.0 + random(&mut seed) * random(&mut seed) * 40.0;
+                let y = center * 10.0 - 20.0 + random(&mut seed) * random(&mut seed) * 40.0;
+                Feature {
+                    geometry: Some(Geometry::new(Point(vec![x, y]))),
+                    properties: Some(JsonObject::from_iter([
+                        ("value".to_string(), json!(value)),
+                        ("weight".to_string(), json!(1 + value % 3)),
+                    ])),
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        let mut index = Supercluster::new(Options {
+            reducer: Some(Arc::new(Sum)),
+            weight_property: Some("weight".to_string()),
+            ..Options::default()
+        });
+        index.load(points).unwrap();
+        index
+    }
+
+    fn value(feature: &Feature) -> u64 {
+        feature.property("value").unwrap().as_u64().unwrap()
+    }
+
+    fn cluster_id(feature: &Feature) -> Option<usize> {
+        feature.property("cluster_id").map(|id| id.as_u64().unwrap() as usize)
+    }
+
+    #[test]
+    fn test_select_all_matches_index() {
+        let index = index();
+        let selection = index.select(vec![true; index.points.len()]).unwrap();
+        let selected = index.with_selection(Some(&selection));
+
+        for zoom in 0..=17 {
+            let clusters = index.get_clusters(WORLD, zoom);
+            assert_eq!(selected.get_clusters(WORLD, zoom), clusters);
+
+            for id in clusters.iter().filter_map(cluster_id) {
+                assert_eq!(selected.get_children(id), index.get_children(id));
+                assert_eq!(selected.get_leaves(id, usize::MAX, 0), index.get_leaves(id, usize::MAX, 0));
+                assert_eq!(selected.get_cluster_expansion_zoom(id), index.get_cluster_expansion_zoom(id));
+            }
+        }
+
+        for z in 0..4 {
+            for x in 0..1 << z {
+                for y in 0..1 << z {
+                    let (x, y) = (x as f64, y as f64);
+                    assert_eq!(selected.get_tile(z, x, y), index.get_tile(z, x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_reduces_clusters() {
+        let index = index();
+        let selected: Vec<bool> = (0..index.points.len()).map(|i| i % 3 == 0).collect();
+        let selection = index.select(selected.clone()).unwrap();
+        let query = index.with_selection(Some(&selection));
+
+        for zoom in 0..=17 {
+            let mut values = vec![];
+
+            for feature in query.get_clusters(WORLD, zoom) {
+                let id = match cluster_id(&feature) {
+                    Some(id) => id,
+                    None => {
+                        values.push(value(&feature));
+                        continue;
+                    }
+                };
+
+                // Clusters stand for their selected points, weighted around their center
+                let leaves = query.get_leaves(id, usize::MAX, 0);
+                let sum: u64 = leaves.iter().map(value).sum();
+                let weights: Vec<f64> = leaves.iter().map(|leaf| (1 + value(leaf) % 3) as f64).collect();
+                let weight: f64 = weights.iter().sum();
+                let center = |project: fn(f64) -> f64, axis: usize| {
+                    let coordinates = leaves.iter().map(|leaf| match &leaf.geometry.as_ref().unwrap().value {
+                        Point(coordinates) => project(coordinates[axis]),
+                        _ => unreachable!(),
+                    });
+                    coordinates.zip(&weights).map(|(c, w)| c * w).sum::<f64>() / weight
+                };
+
+                assert!(leaves.len() > 1);
+                assert_eq!(feature.property("point_count"), Some(&json!(leaves.len())));
+                assert_eq!(feature.property("sum"), Some(&json!(sum)));
+                assert_eq!(feature.property("finished"), Some(&json!(true)));
+                assert_eq!(feature.property("weight_sum"), Some(&json!(weight)));
+                let (lng, lat) = CoordinateSystem::Geographic.unproject(center(lng_x, 0), center(lat_y, 1));
+                match &feature.geometry.as_ref().unwrap().value {
+                    Point(coordinates) => {
+                        assert!((coordinates[0] - lng).abs() < 1e-9 && (coordinates[1] - lat).abs() < 1e-9)
+                    }
+                    _ => unreachable!(),
+                }
+
+                let children = query.get_children(id).unwrap();
+                let count = |child: &Feature| child.property("point_count").map_or(1, |count| count.as_u64().unwrap());
+                assert_eq!(children.iter().map(count).sum::<u64>(), leaves.len() as u64);
+                values.extend(leaves.iter().map(value));
+            }
+
+            // Every selected point is in exactly one cluster or on its own
+            values.sort_unstable();
+            let expected: Vec<u64> = (0..selected.len() as u64).filter(|&i| selected[i as usize]).collect();
+            assert_eq!(values, expected, "zoom {}", zoom);
+        }
+    }
+
+    #[test]
+    fn test_select_nothing() {
+        let index = index();
+        let selection = index.select(vec![false; index.points.len()]).unwrap();
+        let query = index.with_selection(Some(&selection));
+
+        for zoom in 0..=17 {
+            assert_eq!(query.get_clusters(WORLD, zoom), vec![]);
+            assert!(query.get_cluster_points(WORLD, zoom).is_empty());
+        }
+        assert_eq!(query.get_tile(0, 0.0, 0.0), None);
+
+        // Clusters of the index keep their IDs, without children
+        let id = index.get_clusters(WORLD, 0).iter().find_map(cluster_id).unwrap();
+        assert_eq!(query.get_children(id), Ok(vec![]));
+        assert_eq!(query.get_leaves(id, 10, 0), vec![]);
+        assert!(query.get_children(id + (index.points.len() << 5)).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! Storage of the index, either owned or borrowed from a serialized index such as a memory-mapped
+//! file, in which case nothing is copied until it is modified or accessed.
+
+use bytemuck::Pod;
+use std::borrow::Cow;
+use std::fmt::{self, Debug};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+/// Shared bytes of a serialized index, e.g. a memory map.
+pub type Bytes = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+/// An array of numbers, either owned or stored in serialized bytes.
+#[derive(Clone)]
+pub enum Column<T> {
+    Owned(Vec<T>),
+
+    /// `len` numbers starting at byte `start`, which is aligned for `T`.
+    Mapped { bytes: Bytes, start: usize, len: usize },
+}
+
+impl<T: Pod> Deref for Column<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Column::Owned(values) => values,
+            Column::Mapped { bytes, start, len } => {
+                let end = start + len * std::mem::size_of::<T>();
+                bytemuck::cast_slice(&(**bytes).as_ref()[*start..end])
+            }
+        }
+    }
+}
+
+/// Modifying a stored array first copies it.
+impl<T: Pod> DerefMut for Column<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        if let Column::Mapped { .. } = self {
+            *self = Column::Owned(self.to_vec());
+        }
+        match self {
+            Column::Owned(values) => values,
+            Column::Mapped { .. } => unreachable!(),
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for Column<T> {
+    fn from(values: Vec<T>) -> Self {
+        Column::Owned(values)
+    }
+}
+
+impl<T: Pod + Debug> Debug for Column<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// A record of a serialized index.
+pub trait Record: Clone {
+    /// Serialize the record.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Deserialize a record written by `Record::encode`, or describe why it is corrupt.
+    fn decode(bytes: &[u8]) -> Result<Self, String>;
+}
+
+/// A sequence of records, either owned or stored in serialized bytes and decoded when accessed.
+#[derive(Clone)]
+pub enum Records<T> {
+    Owned(Vec<T>),
+
+    /// `count + 1` `u64` offsets starting at byte `table`, relative to byte `body`, delimiting
+    /// `count` records. The offsets are checked to be ascending and within the bytes, and the records
+    /// to decode.
+    Mapped {
+        bytes: Bytes,
+        table: usize,
+        count: usize,
+        body: usize,
+    },
+}
+
+impl<T: Record> Records<T> {
+    pub fn len(&self) -> usize {
+        match self {
+            Records::Owned(records) => records.len(),
+            Records::Mapped { count, .. } => *count,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The record at `index`, decoded if it is stored.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds. Stored records are checked to decode by `Supercluster::from_mapped`.
+    pub fn get(&self, index: usize) -> Cow<'_, T> {
+        match self {
+            Records::Owned(records) => Cow::Borrowed(&records[index]),
+            Records::Mapped { bytes, table, count, body } => {
+                assert!(index < *count, "record index out of bounds");
+                let bytes = (**bytes).as_ref();
+                let offset = |i: usize| {
+                    let at = table + 8 * i;
+                    body + u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize
+                };
+                let record = T::decode(&bytes[offset(index)..offset(index + 1)]);
+                Cow::Owned(record.expect("stored records are checked when mapped"))
+            }
+        }
+    }
+
+    /// All records, decoded if they are stored.
+    pub fn to_vec(&self) -> Vec<T> {
+        match self {
+            Records::Owned(records) => records.clone(),
+            Records::Mapped { .. } => (0..self.len()).map(|i| self.get(i).into_owned()).collect(),
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for Records<T> {
+    fn from(records: Vec<T>) -> Self {
+        Records::Owned(records)
+    }
+}
+
+impl<T: Debug> Debug for Records<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Records::Owned(records) => records.fmt(f),
+            Records::Mapped { count, .. } => write!(f, "[{} stored records]", count),
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! Helpers shared by the tests of the cluster property reducers.
+
+use geojson::JsonObject;
+use serde_json::Value;
+
+use crate::supercluster::Reducer;
+
+/// Accumulate points the way the engine does, then merge clusters of them
+/// into one, and return the finished properties of that cluster.
+pub fn cluster(reducer: &dyn Reducer, clusters: &[&[Value]]) -> JsonObject {
+    let accumulated: Vec<JsonObject> = clusters
+        .iter()
+        .map(|points| {
+            let mut mapped = points.iter().map(|point| reducer.map(point.as_object().unwrap()).unwrap());
+            let mut accumulated = mapped.next().unwrap();
+            for properties in mapped {
+                reducer.reduce(&mut accumulated, &properties).unwrap();
+            }
+            accumulated
+        })
+        .collect();
+    let mut merged = accumulated[0].clone();
+    for properties in &accumulated[1..] {
+        reducer.reduce(&mut merged, properties).unwrap();
+    }
+    reducer.finish(&mut merged);
+    merged
+}
@@ -5,3 +5,10 @@ js = json.load(open('points.json'))
 x.load(js)
 r = x.get_clusters([-180, -85, 180, 85], 10)
 print(r[:10])
+
+cluster = next(f for f in r if f['properties'].get('cluster'))
+print(x.get_leaves(cluster['properties']['cluster_id'], limit=5))
+print(x.get_children(cluster['properties']['cluster_id']))
+print(x.get_tile(0, 0, 0))
+print(len(x.get_tile_mvt(0, 0, 0)))
+print(len(pysupercluster.PySupercluster.from_bytes(x.to_bytes()).get_clusters([-180, -85, 180, 85], 10)))